@@ -6,7 +6,7 @@ fn main() {
     embed_resource::compile("app.rc", embed_resource::NONE);
     slint_build::compile("ui/settingswindow.slint").unwrap();
 
-    let semver_regex = Regex::new(r"^v\d+(\.\d+)*$").unwrap();
+    let semver_regex = Regex::new(r"^v\d+(\.\d+)*(-[0-9A-Za-z.-]+)?(\+[0-9A-Za-z.-]+)?$").unwrap();
 
     // Reading the GITHUB_REF_NAME environment variable set by GitHub Actions
     let version = match env::var("GITHUB_REF_NAME") {
@@ -24,4 +24,7 @@ fn main() {
         }
     };
     println!("cargo:rustc-env=APP_VERSION={}", version);
+    println!("cargo:rerun-if-env-changed=GITHUB_REF_NAME");
+    // Compiled in by option_env! in release.rs
+    println!("cargo:rerun-if-env-changed=UPDATE_PUBLIC_KEY");
 }
\ No newline at end of file
@@ -0,0 +1,283 @@
+use std::{ptr::null_mut, sync::mpsc, thread::{self, JoinHandle}, time::{Duration, Instant}};
+use serde::{Serialize, Deserialize};
+use winapi::um::processthreadsapi::GetCurrentThreadId;
+use winapi::um::winuser::{GetMessageW, PostThreadMessageW, RegisterHotKey, UnregisterHotKey, MOD_ALT, MOD_CONTROL, MOD_NOREPEAT, MOD_SHIFT, MOD_WIN, MSG, VK_F1, WM_HOTKEY, WM_QUIT};
+
+
+const TAP_SEQUENCE_TIMEOUT: Duration = Duration::from_secs(5);
+const CORNER_SIZE: f32 = 0.1;
+const HOTKEY_ID: i32 = 1;
+
+#[derive(PartialEq, Debug, Serialize, Deserialize, Clone, Copy)]
+pub enum ScreenCorner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+/// Secret gesture that reveals the hidden admin panel.
+#[derive(PartialEq, Debug, Serialize, Deserialize, Clone)]
+pub enum AdminGesture {
+    CornerTaps(Vec<ScreenCorner>),
+    LongPress { seconds: u64 },
+    /// e.g. "Ctrl+Alt+K", registered system wide because the client application usually has the focus
+    Hotkey(String),
+}
+
+impl Default for AdminGesture {
+    fn default() -> Self {
+        AdminGesture::CornerTaps(vec![ScreenCorner::TopLeft, ScreenCorner::TopRight, ScreenCorner::BottomRight, ScreenCorner::BottomLeft])
+    }
+}
+
+pub struct GestureDetector {
+    gesture: AdminGesture,
+    taps: Vec<ScreenCorner>,
+    last_tap: Option<Instant>,
+    pressed_at: Option<Instant>,
+}
+
+impl GestureDetector {
+    pub fn new(gesture: AdminGesture) -> Self {
+        Self { gesture, taps: Vec::new(), last_tap: None, pressed_at: None }
+    }
+
+    /// Returns true when the tap completes the configured corner sequence.
+    pub fn pointer_down(&mut self, x: f32, y: f32, width: f32, height: f32) -> bool {
+        let now = Instant::now();
+        self.pressed_at = Some(now);
+
+        let AdminGesture::CornerTaps(sequence) = &self.gesture else {
+            return false;
+        };
+        if sequence.is_empty() {
+            return false;
+        }
+
+        if self.last_tap.is_some_and(|last_tap| now.duration_since(last_tap) > TAP_SEQUENCE_TIMEOUT) {
+            self.taps.clear();
+        }
+        self.last_tap = Some(now);
+
+        let Some(corner) = corner_at(x, y, width, height) else {
+            self.taps.clear();
+            return false;
+        };
+
+        self.taps.push(corner);
+        if !sequence.starts_with(&self.taps) {
+            self.taps.clear();
+            if sequence[0] == corner {
+                self.taps.push(corner);
+            }
+        }
+
+        if self.taps == *sequence {
+            self.taps.clear();
+            return true;
+        }
+        false
+    }
+
+    /// Returns true when the released press was held long enough.
+    pub fn pointer_up(&mut self) -> bool {
+        let pressed_at = self.pressed_at.take();
+        match self.gesture {
+            AdminGesture::LongPress { seconds } => pressed_at.is_some_and(|pressed_at| pressed_at.elapsed() >= Duration::from_secs(seconds)),
+            _ => false,
+        }
+    }
+}
+
+fn corner_at(x: f32, y: f32, width: f32, height: f32) -> Option<ScreenCorner> {
+    let left = x <= width * CORNER_SIZE;
+    let right = x >= width * (1.0 - CORNER_SIZE);
+    let top = y <= height * CORNER_SIZE;
+    let bottom = y >= height * (1.0 - CORNER_SIZE);
+
+    match (left, right, top, bottom) {
+        (true, _, true, _) => Some(ScreenCorner::TopLeft),
+        (_, true, true, _) => Some(ScreenCorner::TopRight),
+        (true, _, _, true) => Some(ScreenCorner::BottomLeft),
+        (_, true, _, true) => Some(ScreenCorner::BottomRight),
+        _ => None,
+    }
+}
+
+/// Parses "Ctrl+Alt+K" into RegisterHotKey modifiers and virtual key code.
+fn parse_hotkey(hotkey: &str) -> Option<(u32, u32)> {
+    let mut modifiers = MOD_NOREPEAT as u32;
+    let mut key = None;
+
+    for part in hotkey.split('+').map(|part| part.trim().to_uppercase()) {
+        match part.as_str() {
+            "CTRL" | "CONTROL" => modifiers |= MOD_CONTROL as u32,
+            "ALT" => modifiers |= MOD_ALT as u32,
+            "SHIFT" => modifiers |= MOD_SHIFT as u32,
+            "WIN" => modifiers |= MOD_WIN as u32,
+            _ if part.len() == 1 && part.chars().all(|c| c.is_ascii_alphanumeric()) => key = part.chars().next().map(|c| c as u32),
+            _ if part.starts_with('F') => {
+                let number = part[1..].parse::<u32>().ok().filter(|number| (1..=24).contains(number))?;
+                key = Some(VK_F1 as u32 + number - 1);
+            },
+            _ => return None,
+        }
+    }
+
+    key.map(|key| (modifiers, key))
+}
+
+pub struct HotkeyListener {
+    thread_id: u32,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl HotkeyListener {
+    pub fn spawn(hotkey: &str, on_pressed: impl Fn() + Send + 'static) -> Option<Self> {
+        let (modifiers, key) = parse_hotkey(hotkey)?;
+        let (sender, receiver) = mpsc::channel();
+
+        let worker = thread::spawn(move || unsafe {
+            // The hotkey messages are posted to the queue of the registering thread.
+            if RegisterHotKey(null_mut(), HOTKEY_ID, modifiers, key) == 0 {
+                let _ = sender.send(None);
+                return;
+            }
+            let _ = sender.send(Some(GetCurrentThreadId()));
+
+            let mut msg: MSG = std::mem::zeroed();
+            while GetMessageW(&mut msg, null_mut(), 0, 0) > 0 {
+                if msg.message == WM_HOTKEY {
+                    on_pressed();
+                }
+            }
+            UnregisterHotKey(null_mut(), HOTKEY_ID);
+        });
+
+        match receiver.recv() {
+            Ok(Some(thread_id)) => Some(Self { thread_id, worker: Some(worker) }),
+            _ => {
+                let _ = worker.join();
+                None
+            }
+        }
+    }
+}
+
+impl Drop for HotkeyListener {
+    fn drop(&mut self) {
+        unsafe {
+            PostThreadMessageW(self.thread_id, WM_QUIT, 0, 0);
+        }
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTH: f32 = 1920.0;
+    const HEIGHT: f32 = 1080.0;
+
+    fn tap(detector: &mut GestureDetector, corner: ScreenCorner) -> bool {
+        let (x, y) = match corner {
+            ScreenCorner::TopLeft => (10.0, 10.0),
+            ScreenCorner::TopRight => (WIDTH - 10.0, 10.0),
+            ScreenCorner::BottomLeft => (10.0, HEIGHT - 10.0),
+            ScreenCorner::BottomRight => (WIDTH - 10.0, HEIGHT - 10.0),
+        };
+        detector.pointer_down(x, y, WIDTH, HEIGHT)
+    }
+
+    #[test]
+    fn corner_sequence_opens_only_when_complete() {
+        let mut detector = GestureDetector::new(AdminGesture::default());
+        assert!(!tap(&mut detector, ScreenCorner::TopLeft));
+        assert!(!tap(&mut detector, ScreenCorner::TopRight));
+        assert!(!tap(&mut detector, ScreenCorner::BottomRight));
+        assert!(tap(&mut detector, ScreenCorner::BottomLeft));
+        // The sequence starts over after it was recognized.
+        assert!(!tap(&mut detector, ScreenCorner::BottomLeft));
+    }
+
+    #[test]
+    fn wrong_corner_or_tap_outside_restarts_the_sequence() {
+        let mut detector = GestureDetector::new(AdminGesture::default());
+        tap(&mut detector, ScreenCorner::TopLeft);
+        tap(&mut detector, ScreenCorner::BottomLeft);
+        assert!(!tap(&mut detector, ScreenCorner::BottomRight));
+
+        tap(&mut detector, ScreenCorner::TopLeft);
+        tap(&mut detector, ScreenCorner::TopRight);
+        assert!(!detector.pointer_down(WIDTH / 2.0, HEIGHT / 2.0, WIDTH, HEIGHT));
+        assert!(!tap(&mut detector, ScreenCorner::BottomRight));
+
+        // A wrong tap on the first corner counts as the start of a new sequence.
+        tap(&mut detector, ScreenCorner::TopLeft);
+        tap(&mut detector, ScreenCorner::TopLeft);
+        tap(&mut detector, ScreenCorner::TopRight);
+        tap(&mut detector, ScreenCorner::BottomRight);
+        assert!(tap(&mut detector, ScreenCorner::BottomLeft));
+    }
+
+    #[test]
+    fn slow_taps_restart_the_sequence() {
+        let mut detector = GestureDetector::new(AdminGesture::default());
+        tap(&mut detector, ScreenCorner::TopLeft);
+        tap(&mut detector, ScreenCorner::TopRight);
+        tap(&mut detector, ScreenCorner::BottomRight);
+        detector.last_tap = Some(Instant::now() - TAP_SEQUENCE_TIMEOUT - Duration::from_secs(1));
+        assert!(!tap(&mut detector, ScreenCorner::BottomLeft));
+    }
+
+    #[test]
+    fn long_press_opens_only_when_held_long_enough() {
+        let mut detector = GestureDetector::new(AdminGesture::LongPress { seconds: 5 });
+        assert!(!detector.pointer_down(WIDTH / 2.0, HEIGHT / 2.0, WIDTH, HEIGHT));
+        assert!(!detector.pointer_up());
+
+        detector.pointer_down(WIDTH / 2.0, HEIGHT / 2.0, WIDTH, HEIGHT);
+        detector.pressed_at = Some(Instant::now() - Duration::from_secs(5));
+        assert!(detector.pointer_up());
+        // A release without a press, e.g. after the panel opened, does not count.
+        assert!(!detector.pointer_up());
+    }
+
+    #[test]
+    fn corner_taps_are_not_a_long_press() {
+        let mut detector = GestureDetector::new(AdminGesture::default());
+        tap(&mut detector, ScreenCorner::TopLeft);
+        detector.pressed_at = Some(Instant::now() - Duration::from_secs(60));
+        assert!(!detector.pointer_up());
+    }
+
+    #[test]
+    fn hotkey_modifiers_are_combined() {
+        let modifiers = MOD_NOREPEAT as u32 | MOD_CONTROL as u32 | MOD_ALT as u32;
+        assert_eq!(parse_hotkey("Ctrl+Alt+K"), Some((modifiers, 'K' as u32)));
+        assert_eq!(parse_hotkey(" control + alt + k "), Some((modifiers, 'K' as u32)));
+        assert_eq!(parse_hotkey("Win+Shift+7"), Some((MOD_NOREPEAT as u32 | MOD_WIN as u32 | MOD_SHIFT as u32, '7' as u32)));
+    }
+
+    #[test]
+    fn hotkey_function_keys_go_up_to_f24() {
+        assert_eq!(parse_hotkey("Ctrl+F1"), Some((MOD_NOREPEAT as u32 | MOD_CONTROL as u32, VK_F1 as u32)));
+        assert_eq!(parse_hotkey("F24"), Some((MOD_NOREPEAT as u32, VK_F1 as u32 + 23)));
+        assert_eq!(parse_hotkey("Ctrl+F"), Some((MOD_NOREPEAT as u32 | MOD_CONTROL as u32, 'F' as u32)));
+        assert_eq!(parse_hotkey("F0"), None);
+        assert_eq!(parse_hotkey("F25"), None);
+    }
+
+    #[test]
+    fn invalid_hotkeys_are_rejected() {
+        assert_eq!(parse_hotkey(""), None);
+        assert_eq!(parse_hotkey("Ctrl+Alt"), None);
+        assert_eq!(parse_hotkey("Ctrl+Alt+Del"), None);
+        assert_eq!(parse_hotkey("Ctrl++K"), None);
+        assert_eq!(parse_hotkey("Hyper+K"), None);
+    }
+}
@@ -0,0 +1,371 @@
+use std::{error::Error, fs::{self, File}, os::windows::process::CommandExt, path::{Path, PathBuf}, process::Command, sync::Mutex};
+use lazy_static::lazy_static;
+use reqwest::Url;
+use serde::{Serialize, Deserialize};
+use zip::ZipArchive;
+
+use crate::{audit_log::AuditLog, http_client, machinepolicy::MachinePolicy, mainconfig::ConfigManager, release::{self, DownloadOptions}, update_source};
+
+
+/// Client application exits before it counts as healthy, after which a new version is rolled back.
+const MAX_CRASHES: u32 = 3;
+
+/// Published next to the client application package, e.g. "https://apps.corp.local/kiosk-app/manifest.json", and signed
+/// like the package ("manifest.json.minisig"). How the package is installed is up to the machine policy, never the manifest.
+#[derive(Debug, Clone, Deserialize)]
+pub struct AppManifest {
+    pub version: String,
+    /// Package URL, relative to the manifest or absolute
+    pub url: String,
+    pub sha256: String,
+    /// Detached minisign signature, "<url>.minisig" if not given
+    pub signature_url: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct StagedApp {
+    pub manifest: AppManifest,
+    pub path: PathBuf,
+}
+
+/// Progress of a client application deployment, kept across restarts of the shell.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct DeploymentState {
+    installed_version: Option<String>,
+    pending_version: Option<String>,
+    previous_folder: Option<PathBuf>,
+    app_folder: Option<PathBuf>,
+    crashes: u32,
+    failed_versions: Vec<String>,
+}
+
+impl DeploymentState {
+    fn load() -> Self {
+        confy::load_path(Self::get_state_file_path()).unwrap_or_default()
+    }
+
+    fn save(&self) {
+        let _ = confy::store_path(Self::get_state_file_path(), self);
+    }
+
+    fn get_state_file_path() -> PathBuf {
+        let mut file_path = ConfigManager::get_configuration_file_path(&"");
+        file_path.set_file_name("app_deployment.ron");
+        file_path
+    }
+}
+
+lazy_static! {
+    static ref STAGED_APP: Mutex<Option<StagedApp>> = Mutex::new(None);
+}
+
+pub fn staged_app() -> Option<StagedApp> {
+    STAGED_APP.lock().unwrap().clone()
+}
+
+/// Downloads and verifies a new client application version from `app_manifest_url`; returns the staged version.
+pub fn check_for_app_update(is_cancelled: &dyn Fn() -> bool) -> Result<Option<String>, Box<dyn Error>> {
+    let Some(manifest_url) = ConfigManager::load_config(&"").app_manifest_url else {
+        return Ok(None);
+    };
+    let policy = MachinePolicy::load();
+    if policy.app_extract_to.is_none() {
+        return Err("AppExtractTo is not set in the machine policy".into());
+    }
+    let public_key = policy.app_public_key.clone()
+        .or_else(release::update_public_key)
+        .ok_or("no public key to verify the application signature")?;
+
+    // Nothing in the manifest is used before its signature is checked.
+    let client = http_client::client(&policy)?;
+    let manifest_url = Url::parse(&manifest_url)?;
+    release::check_cancelled(is_cancelled)?;
+    let manifest_data = update_source::http_get(&client, manifest_url.as_str())?.bytes()?;
+    release::check_cancelled(is_cancelled)?;
+    let manifest_signature = update_source::http_get(&client, &format!("{}.minisig", manifest_url))?.text()?;
+    if let Err(e) = release::verify_signature(&manifest_data, &manifest_signature, &public_key) {
+        AuditLog::write("app_manifest_signature_invalid", &format!("{}: {}", manifest_url, e));
+        return Err(e.into());
+    }
+    let manifest: AppManifest = serde_json::from_slice(&manifest_data)?;
+
+    let state = DeploymentState::load();
+    if state.installed_version.as_ref() == Some(&manifest.version) || state.failed_versions.contains(&manifest.version) {
+        return Ok(None);
+    }
+    if staged_app().is_some_and(|staged| staged.manifest.version == manifest.version) {
+        return Ok(Some(manifest.version));
+    }
+
+    let package_url = manifest_url.join(&manifest.url)?;
+    let signature_url = match &manifest.signature_url {
+        Some(signature_url) => manifest_url.join(signature_url)?,
+        None => Url::parse(&format!("{}.minisig", package_url))?,
+    };
+    release::check_cancelled(is_cancelled)?;
+    let signature = update_source::http_get(&client, signature_url.as_str())?.text()?;
+
+    let staging_directory = get_staging_directory();
+    fs::create_dir_all(&staging_directory)?;
+    let file_name = package_url.path_segments().and_then(|segments| segments.last()).filter(|name| !name.is_empty()).unwrap_or("package");
+    let path = staging_directory.join(format!("{}-{}", manifest.version, file_name));
+
+    let options = DownloadOptions {
+        bytes_per_sec: ConfigManager::load_config(&"").download_limit_kb_per_sec.map(|limit| limit * 1024),
+        on_progress: &|_, _| {},
+        is_cancelled,
+    };
+    let download_client = http_client::download_client(&policy)?;
+    release::download_verified(&|offset| update_source::http_get_at(&download_client, package_url.as_str(), offset),
+        package_url.as_str(), &manifest.sha256, &signature, &public_key, &path, &options)?;
+
+    AuditLog::write("app_update_staged", &manifest.version);
+    let version = manifest.version.clone();
+    *STAGED_APP.lock().unwrap() = Some(StagedApp { manifest, path });
+    Ok(Some(version))
+}
+
+/// Installs the staged version; the client application must not be running. The old folder is kept for a rollback.
+pub fn install_staged_app() -> Result<Option<String>, Box<dyn Error>> {
+    let policy = MachinePolicy::load();
+    let app_folder = policy.app_extract_to.ok_or("AppExtractTo is not set in the machine policy")?;
+    let Some(staged) = STAGED_APP.lock().unwrap().take() else {
+        return Ok(None);
+    };
+    let version = staged.manifest.version.clone();
+    // On the first deployment there is nothing to back up and nothing to roll back to.
+    let previous_folder = app_folder.exists().then(|| get_staging_directory().join("previous"));
+
+    if let Err(e) = install_with_backup(&staged, policy.app_install_command.as_deref(), &app_folder, previous_folder.as_deref()) {
+        AuditLog::write("app_install_failed", &format!("{}: {}", version, e));
+        return Err(e);
+    }
+    let _ = fs::remove_file(&staged.path);
+
+    let mut state = DeploymentState::load();
+    state.pending_version = Some(version.clone());
+    state.previous_folder = previous_folder;
+    state.app_folder = Some(app_folder);
+    state.crashes = 0;
+    state.save();
+    AuditLog::write("app_installed", &format!("{} -> {}", state.installed_version.clone().unwrap_or_default(), version));
+    Ok(Some(version))
+}
+
+/// Backs up the application folder to `previous_folder`, if given, and restores it when the installation fails.
+fn install_with_backup(staged: &StagedApp, install_command: Option<&str>, app_folder: &Path, previous_folder: Option<&Path>) -> Result<(), Box<dyn Error>> {
+    if let Some(previous_folder) = previous_folder {
+        backup_folder(app_folder, previous_folder)?;
+    }
+    let result = install(staged, install_command, app_folder);
+    if let (Err(_), Some(previous_folder)) = (&result, previous_folder) {
+        let _ = restore_folder(previous_folder, app_folder);
+    }
+    result
+}
+
+fn install(staged: &StagedApp, install_command: Option<&str>, app_folder: &Path) -> Result<(), Box<dyn Error>> {
+    if let Some(install_command) = install_command {
+        let command = install_command.replace("{file}", &format!("\"{}\"", staged.path.display()));
+        // Passed to cmd as written: std would escape the quotes around the path as \", which cmd does not understand.
+        // With /S cmd only strips the outer pair of quotes, so the command may start with a quoted program path.
+        let status = Command::new("cmd").raw_arg(format!("/S /C \"{}\"", command)).status()?;
+        if !status.success() {
+            return Err(format!("{} failed with {}", command, status).into());
+        }
+        return Ok(());
+    }
+
+    let mut archive = ZipArchive::new(File::open(&staged.path)?)?;
+    if app_folder.exists() {
+        fs::remove_dir_all(app_folder)?;
+    }
+    archive.extract(app_folder)?;
+    Ok(())
+}
+
+pub fn is_app_update_pending() -> bool {
+    DeploymentState::load().pending_version.is_some()
+}
+
+/// The new version kept running long enough.
+pub fn mark_app_healthy() {
+    let mut state = DeploymentState::load();
+    if let Some(version) = state.pending_version.take() {
+        AuditLog::write("app_update_healthy", &version);
+        state.installed_version = Some(version);
+        state.crashes = 0;
+        state.save();
+    }
+}
+
+/// Counts an exit of a freshly installed version; returns the version if it crash-looped and the previous folder was restored.
+pub fn record_app_exit() -> Option<String> {
+    let mut state = DeploymentState::load();
+    let result = record_exit(&mut state);
+    state.save();
+    result
+}
+
+fn record_exit(state: &mut DeploymentState) -> Option<String> {
+    let version = state.pending_version.clone()?;
+    state.crashes += 1;
+    if state.crashes < MAX_CRASHES {
+        return None;
+    }
+
+    state.pending_version = None;
+    match (&state.previous_folder, &state.app_folder) {
+        (Some(previous_folder), Some(app_folder)) => match restore_folder(previous_folder, app_folder) {
+            Ok(()) => AuditLog::write("app_rolled_back", &format!("{} after {} crashes", version, state.crashes)),
+            Err(e) => AuditLog::write("app_rollback_failed", &format!("{}: {}", version, e)),
+        },
+        // The first deployment replaced nothing, the version is only kept from being installed again.
+        _ => AuditLog::write("app_update_failed", &format!("{} after {} crashes, no previous version to restore", version, state.crashes)),
+    }
+    state.failed_versions.push(version.clone());
+    state.crashes = 0;
+    Some(version)
+}
+
+fn backup_folder(app_folder: &Path, previous_folder: &Path) -> Result<(), Box<dyn Error>> {
+    if previous_folder.exists() {
+        fs::remove_dir_all(previous_folder)?;
+    }
+    copy_folder(app_folder, previous_folder)
+}
+
+fn restore_folder(previous_folder: &Path, app_folder: &Path) -> Result<(), Box<dyn Error>> {
+    if app_folder.exists() {
+        fs::remove_dir_all(app_folder)?;
+    }
+    copy_folder(previous_folder, app_folder)
+}
+
+fn copy_folder(source: &Path, target: &Path) -> Result<(), Box<dyn Error>> {
+    fs::create_dir_all(target)?;
+    for entry in fs::read_dir(source)? {
+        let entry = entry?;
+        let target_path = target.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_folder(&entry.path(), &target_path)?;
+        } else {
+            fs::copy(entry.path(), target_path)?;
+        }
+    }
+    Ok(())
+}
+
+fn get_staging_directory() -> PathBuf {
+    let mut directory = ConfigManager::get_configuration_file_path(&"");
+    directory.set_file_name("app");
+    directory
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use crate::test_support::TempPath;
+
+    fn staged(path: &Path) -> StagedApp {
+        let manifest = AppManifest { version: "2.0.0".to_string(), url: "app.zip".to_string(), sha256: String::new(), signature_url: None };
+        StagedApp { manifest, path: path.to_path_buf() }
+    }
+
+    fn write_app(folder: &Path, version: &str) {
+        fs::create_dir_all(folder.join("data")).unwrap();
+        fs::write(folder.join("app.exe"), version).unwrap();
+        fs::write(folder.join("data").join("settings.json"), version).unwrap();
+    }
+
+    fn read_app(folder: &Path) -> (String, String) {
+        (fs::read_to_string(folder.join("app.exe")).unwrap(), fs::read_to_string(folder.join("data").join("settings.json")).unwrap())
+    }
+
+    #[test]
+    fn backup_and_restore_copy_nested_folders() {
+        let app_folder = TempPath::new("app_backup");
+        let previous_folder = TempPath::new("app_backup_previous");
+        write_app(&app_folder, "1.0.0");
+        backup_folder(&app_folder, &previous_folder).unwrap();
+
+        write_app(&app_folder, "2.0.0");
+        fs::write(app_folder.join("new.dll"), "2.0.0").unwrap();
+        restore_folder(&previous_folder, &app_folder).unwrap();
+        assert_eq!(read_app(&app_folder), ("1.0.0".to_string(), "1.0.0".to_string()));
+        assert!(!app_folder.join("new.dll").exists());
+    }
+
+    #[test]
+    fn first_deployment_extracts_into_a_missing_folder() {
+        let package = TempPath::new("app_package.zip");
+        let mut zip = zip::ZipWriter::new(File::create(&package).unwrap());
+        zip.start_file("app.exe", zip::write::SimpleFileOptions::default()).unwrap();
+        zip.write_all(b"2.0.0").unwrap();
+        zip.finish().unwrap();
+
+        let app_folder = TempPath::new("app_first");
+        install_with_backup(&staged(&package), None, &app_folder, None).unwrap();
+        assert_eq!(fs::read_to_string(app_folder.join("app.exe")).unwrap(), "2.0.0");
+    }
+
+    #[test]
+    fn failed_installation_restores_the_previous_folder() {
+        let package = TempPath::new("app_broken.zip");
+        fs::write(&package, "not a zip").unwrap();
+        let app_folder = TempPath::new("app_failed");
+        let previous_folder = TempPath::new("app_failed_previous");
+        write_app(&app_folder, "1.0.0");
+
+        assert!(install_with_backup(&staged(&package), None, &app_folder, Some(&previous_folder)).is_err());
+        assert_eq!(read_app(&app_folder), ("1.0.0".to_string(), "1.0.0".to_string()));
+        assert_eq!(read_app(&previous_folder), ("1.0.0".to_string(), "1.0.0".to_string()));
+    }
+
+    #[test]
+    fn crash_loop_rolls_back_after_max_crashes() {
+        let app_folder = TempPath::new("app_crashing");
+        let previous_folder = TempPath::new("app_crashing_previous");
+        write_app(&app_folder, "2.0.0");
+        write_app(&previous_folder, "1.0.0");
+        let mut state = DeploymentState {
+            pending_version: Some("2.0.0".to_string()),
+            previous_folder: Some(previous_folder.to_path_buf()),
+            app_folder: Some(app_folder.to_path_buf()),
+            ..DeploymentState::default()
+        };
+
+        assert_eq!(record_exit(&mut state), None);
+        assert_eq!(record_exit(&mut state), None);
+        assert_eq!(state.crashes, 2);
+        assert_eq!(read_app(&app_folder).0, "2.0.0");
+
+        assert_eq!(record_exit(&mut state), Some("2.0.0".to_string()));
+        assert_eq!(read_app(&app_folder), ("1.0.0".to_string(), "1.0.0".to_string()));
+        assert_eq!((state.pending_version, state.crashes, state.failed_versions), (None, 0, vec!["2.0.0".to_string()]));
+
+        // Without a pending version exits are not counted.
+        let mut state = DeploymentState::default();
+        assert_eq!(record_exit(&mut state), None);
+        assert_eq!(state.crashes, 0);
+    }
+
+    #[test]
+    fn crash_loop_of_the_first_deployment_skips_the_version() {
+        let app_folder = TempPath::new("app_first_crashing");
+        write_app(&app_folder, "2.0.0");
+        let mut state = DeploymentState {
+            pending_version: Some("2.0.0".to_string()),
+            app_folder: Some(app_folder.to_path_buf()),
+            ..DeploymentState::default()
+        };
+        for _ in 1..MAX_CRASHES {
+            assert_eq!(record_exit(&mut state), None);
+        }
+        assert_eq!(record_exit(&mut state), Some("2.0.0".to_string()));
+        assert_eq!(state.failed_versions, ["2.0.0"]);
+        assert_eq!(read_app(&app_folder).0, "2.0.0");
+    }
+}
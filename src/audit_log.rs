@@ -0,0 +1,27 @@
+use std::{fs::{self, OpenOptions}, io::Write, path::PathBuf};
+use chrono::Local;
+
+use crate::mainconfig::ConfigManager;
+
+
+pub struct AuditLog;
+
+impl AuditLog {
+    pub fn write(event: &str, details: &str) {
+        let file_path = Self::get_log_file_path();
+        if let Some(parent) = file_path.parent() {
+            let _ = fs::create_dir_all(parent);
+        }
+
+        if let Ok(mut file) = OpenOptions::new().create(true).append(true).open(&file_path) {
+            let _ = writeln!(file, "{}\t{}\t{}\t{}", Local::now().to_rfc3339(), whoami::username(), event, details);
+        }
+    }
+
+
+    pub fn get_log_file_path() -> PathBuf {
+        let mut file_path = ConfigManager::get_configuration_file_path(&"");
+        file_path.set_file_name("audit.log");
+        file_path
+    }
+}
@@ -0,0 +1,64 @@
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use sha2::Sha256;
+
+
+// Without 0/O and 1/I, which are easily confused on the phone.
+const CHALLENGE_ALPHABET: &[u8] = b"23456789ABCDEFGHJKLMNPQRSTUVWXYZ";
+const CHALLENGE_LENGTH: usize = 6;
+
+pub fn generate_challenge() -> String {
+    let mut rng = rand::thread_rng();
+    (0..CHALLENGE_LENGTH)
+        .map(|_| CHALLENGE_ALPHABET[rng.gen_range(0..CHALLENGE_ALPHABET.len())] as char)
+        .collect()
+}
+
+/// Eight digits of HMAC-SHA256(secret, device ID and challenge), formatted as "1234-5678" to be read out on the phone.
+pub fn compute_response(secret: &str, device_id: &str, challenge: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.trim().as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(signed_message(device_id, challenge).as_bytes());
+    let hash = mac.finalize().into_bytes();
+
+    let number = u64::from_be_bytes(hash[..8].try_into().unwrap()) % 100_000_000;
+    format!("{:04}-{:04}", number / 10_000, number % 10_000)
+}
+
+/// Ignores spaces and dashes, so the response can be typed in any grouping.
+pub fn verify_response(secret: &str, device_id: &str, challenge: &str, response: &str) -> bool {
+    let normalized: Vec<u8> = response.bytes().filter(u8::is_ascii_digit).collect();
+    let expected: Vec<u8> = compute_response(secret, device_id, challenge).bytes().filter(u8::is_ascii_digit).collect();
+    !secret.trim().is_empty() && !challenge.trim().is_empty() && normalized.len() == expected.len()
+        && normalized.iter().zip(&expected).fold(0u8, |difference, (a, b)| difference | (a ^ b)) == 0
+}
+
+fn signed_message(device_id: &str, challenge: &str) -> String {
+    format!("winkioskshell-support\n{}\n{}", device_id.trim().to_uppercase(), challenge.trim().to_uppercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "correct horse battery staple";
+
+    #[test]
+    fn response_is_eight_digits_in_any_grouping() {
+        let response = compute_response(SECRET, "kiosk-01", "ABC234");
+        assert_eq!(response.len(), 9);
+        assert!(response.chars().all(|c| c.is_ascii_digit() || c == '-'));
+        assert!(verify_response(SECRET, "KIOSK-01", "abc234", &response));
+        assert!(verify_response(SECRET, "KIOSK-01", "ABC234", &response.replace('-', " ")));
+    }
+
+    #[test]
+    fn response_is_bound_to_secret_device_and_challenge() {
+        let response = compute_response(SECRET, "kiosk-01", "ABC234");
+        assert!(!verify_response("another secret", "kiosk-01", "ABC234", &response));
+        assert!(!verify_response(SECRET, "kiosk-02", "ABC234", &response));
+        assert!(!verify_response(SECRET, "kiosk-01", "ABC235", &response));
+        assert!(!verify_response(SECRET, "kiosk-01", "", &compute_response(SECRET, "kiosk-01", "")));
+        assert!(!verify_response("", "kiosk-01", "ABC234", &compute_response("", "kiosk-01", "ABC234")));
+        assert!(!verify_response(SECRET, "kiosk-01", "ABC234", &response[..8]));
+    }
+}
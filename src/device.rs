@@ -0,0 +1,12 @@
+use winreg::{enums::HKEY_LOCAL_MACHINE, RegKey};
+
+
+/// Short, phone friendly identifier of this machine: the first block of the Windows MachineGuid.
+pub fn device_id() -> String {
+    RegKey::predef(HKEY_LOCAL_MACHINE)
+        .open_subkey(r"SOFTWARE\Microsoft\Cryptography")
+        .and_then(|key| key.get_value::<String, _>("MachineGuid"))
+        .ok()
+        .and_then(|guid| guid.split('-').next().map(|block| block.to_uppercase()))
+        .unwrap_or_else(|| whoami::devicename().to_uppercase())
+}
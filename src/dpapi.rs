@@ -0,0 +1,38 @@
+use std::{error::Error, io, ptr::null_mut};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use winapi::um::dpapi::{CryptProtectData, CryptUnprotectData, CRYPTPROTECT_LOCAL_MACHINE, CRYPTPROTECT_UI_FORBIDDEN};
+use winapi::um::winbase::LocalFree;
+use winapi::um::wincrypt::DATA_BLOB;
+
+
+/// Encrypts a secret with the machine key, so every account on this machine can read it but no other machine.
+pub fn protect(secret: &str) -> Result<String, Box<dyn Error>> {
+    let mut data = secret.as_bytes().to_vec();
+    let encrypted = transform(&mut data, |input, output| unsafe {
+        CryptProtectData(input, null_mut(), null_mut(), null_mut(), null_mut(), CRYPTPROTECT_LOCAL_MACHINE | CRYPTPROTECT_UI_FORBIDDEN, output)
+    })?;
+    Ok(STANDARD.encode(encrypted))
+}
+
+/// Decrypts the base64 output of `protect`.
+pub fn unprotect(protected: &str) -> Result<String, Box<dyn Error>> {
+    let mut data = STANDARD.decode(protected.trim())?;
+    let decrypted = transform(&mut data, |input, output| unsafe {
+        CryptUnprotectData(input, null_mut(), null_mut(), null_mut(), null_mut(), CRYPTPROTECT_UI_FORBIDDEN, output)
+    })?;
+    Ok(String::from_utf8(decrypted)?)
+}
+
+fn transform(data: &mut [u8], function: impl FnOnce(*mut DATA_BLOB, *mut DATA_BLOB) -> i32) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut input = DATA_BLOB { cbData: data.len() as u32, pbData: data.as_mut_ptr() };
+    let mut output = DATA_BLOB { cbData: 0, pbData: null_mut() };
+    if function(&mut input, &mut output) == 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+
+    unsafe {
+        let result = std::slice::from_raw_parts(output.pbData, output.cbData as usize).to_vec();
+        LocalFree(output.pbData as _);
+        Ok(result)
+    }
+}
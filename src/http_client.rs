@@ -0,0 +1,70 @@
+use std::{error::Error, fs, path::Path, time::Duration};
+use reqwest::{blocking::{Client, ClientBuilder}, Certificate, NoProxy, Proxy};
+
+use crate::{dpapi, machinepolicy::MachinePolicy};
+
+
+/// The client for API and manifest requests, with the proxy, certificates and timeouts from the machine policy.
+pub fn client(policy: &MachinePolicy) -> Result<Client, Box<dyn Error>> {
+    Ok(builder(policy)?
+        .timeout(Duration::from_secs(policy.http_timeout_secs))
+        .build()?)
+}
+
+/// The client for release assets and packages. A large file on a slow or bandwidth limited line can take longer than
+/// any total timeout, so a download only fails when no data arrives for `http_read_timeout_secs`; the blocking client
+/// applies its timeout to each read of the body.
+pub fn download_client(policy: &MachinePolicy) -> Result<Client, Box<dyn Error>> {
+    Ok(builder(policy)?
+        .timeout(Duration::from_secs(policy.http_read_timeout_secs))
+        .build()?)
+}
+
+/// The client for update caches of other kiosks: never through the proxy, and a peer that stops sending is given up
+/// after `http_read_timeout_secs` like any other download.
+pub fn peer_client(policy: &MachinePolicy, connect_timeout: Duration) -> Result<Client, Box<dyn Error>> {
+    Ok(builder(policy)?
+        .no_proxy()
+        .connect_timeout(connect_timeout)
+        .timeout(Duration::from_secs(policy.http_read_timeout_secs))
+        .build()?)
+}
+
+fn builder(policy: &MachinePolicy) -> Result<ClientBuilder, Box<dyn Error>> {
+    let mut builder = Client::builder()
+        .user_agent(concat!(env!("CARGO_PKG_NAME"), "/", env!("APP_VERSION")))
+        .connect_timeout(Duration::from_secs(policy.http_connect_timeout_secs));
+
+    if let Some(proxy_url) = &policy.http_proxy {
+        let mut proxy = Proxy::all(proxy_url)?;
+        if let Some(bypass) = &policy.http_proxy_bypass {
+            // Windows lists entries with semicolons, NO_PROXY with commas.
+            proxy = proxy.no_proxy(NoProxy::from_string(&bypass.replace(';', ",")));
+        }
+        if let Some(user) = &policy.http_proxy_user {
+            let password = match &policy.http_proxy_password {
+                Some(protected) => dpapi::unprotect(protected).map_err(|e| format!("HttpProxyPassword: {}", e))?,
+                None => String::new(),
+            };
+            proxy = proxy.basic_auth(user, &password);
+        }
+        builder = builder.proxy(proxy);
+    }
+
+    for path in &policy.http_root_certificates {
+        builder = builder.add_root_certificate(read_certificate(path).map_err(|e| format!("{}: {}", path.display(), e))?);
+    }
+
+    Ok(builder)
+}
+
+/// PEM or DER, as exported by the certificate manager.
+fn read_certificate(path: &Path) -> Result<Certificate, Box<dyn Error>> {
+    let data = fs::read(path)?;
+    let certificate = if data.starts_with(b"-----BEGIN") {
+        Certificate::from_pem(&data)?
+    } else {
+        Certificate::from_der(&data)?
+    };
+    Ok(certificate)
+}
@@ -0,0 +1,305 @@
+use std::{path::PathBuf, time::{Duration, Instant, SystemTime, UNIX_EPOCH}};
+use serde::{Serialize, Deserialize};
+
+use crate::{audit_log::AuditLog, challenge_response, device, dpapi, machinepolicy::MachinePolicy, mainconfig::{ConfigManager, MainConfig}, totp, unlock_token::UnlockToken};
+
+
+/// Failed attempts survive a restart of the shell, otherwise a reboot would reset the lockout.
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+#[serde(default)]
+pub struct LoginState {
+    pub failed_attempts: u32,
+    pub next_attempt_at: u64,
+    pub locked_until: u64,
+    pub last_totp_step: u64,
+    /// USB unlock tokens work once; kept until they expire
+    pub used_unlock_tokens: Vec<UsedUnlockToken>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize, Clone, PartialEq)]
+pub struct UsedUnlockToken {
+    pub signature: String,
+    pub expires: u64,
+}
+
+/// Operators may restart the client application and reboot, administrators may also leave the kiosk.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum Role {
+    Operator,
+    Administrator,
+}
+
+/// An unlocked role, optionally limited in time (e.g. remote support).
+#[derive(Debug, Clone, Copy)]
+pub struct AdminSession {
+    pub role: Role,
+    pub expires_at: Option<Instant>,
+}
+
+impl AdminSession {
+    pub fn new(role: Role) -> Self {
+        Self { role, expires_at: None }
+    }
+
+    pub fn limited(role: Role, duration: Duration) -> Self {
+        Self { role, expires_at: Some(Instant::now() + duration) }
+    }
+
+    pub fn allows(&self, required: Role) -> bool {
+        self.role >= required && self.remaining().map_or(true, |remaining| !remaining.is_zero())
+    }
+
+    pub fn remaining(&self) -> Option<Duration> {
+        self.expires_at.map(|expires_at| expires_at.saturating_duration_since(Instant::now()))
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub enum LoginResult {
+    Granted(Role),
+    RecoveryAccepted,
+    Denied { failed_attempts: u32, max_attempts: u32 },
+    Throttled { seconds: u64 },
+    LockedOut { seconds: u64 },
+}
+
+impl LoginResult {
+    pub fn granted_role(&self) -> Option<Role> {
+        match self {
+            LoginResult::Granted(role) => Some(*role),
+            _ => None,
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            LoginResult::Granted(_) => String::new(),
+            LoginResult::RecoveryAccepted => "Lockout cleared".to_string(),
+            LoginResult::Denied { failed_attempts, max_attempts } => format!("Wrong password or code ({}/{})", failed_attempts, max_attempts),
+            LoginResult::Throttled { seconds } => format!("Too many attempts, wait {} s", seconds),
+            LoginResult::LockedOut { seconds } => format!("Locked for {} min", seconds.div_ceil(60)),
+        }
+    }
+}
+
+pub struct LoginGuard {
+    state: LoginState,
+    path: PathBuf,
+}
+
+impl LoginGuard {
+    pub fn load() -> Self {
+        let path = Self::get_state_file_path();
+        let state = confy::load_path(&path).unwrap_or_default();
+        Self { state, path }
+    }
+
+    pub fn attempt(&mut self, input: &str, code: &str, config: &MainConfig) -> LoginResult {
+        let now = unix_now();
+        let result = self.evaluate(input, config, now, |guard| {
+            match config.password.as_deref() {
+                None => Some(Role::Administrator),
+                // The administrator password with a wrong code must not fall through to the operator PIN.
+                Some(password) if password == input => guard.verify_second_factor(code, config, now).then_some(Role::Administrator),
+                _ if config.operator_pin.as_deref().is_some_and(|pin| !pin.is_empty() && pin == input) => Some(Role::Operator),
+                _ => None,
+            }
+        });
+
+        AuditLog::write("login_attempt", &format!("{:?}", result));
+        result
+    }
+
+    /// Support responses share the failed attempt counter with passwords, so they cannot be guessed either.
+    pub fn attempt_support_response(&mut self, response: &str, challenge: &str, config: &MainConfig) -> LoginResult {
+        let now = unix_now();
+        let policy = MachinePolicy::load();
+        let device_id = device::device_id();
+        let result = self.evaluate(response, config, now, |_| {
+            let secret = dpapi::unprotect(policy.support_secret.as_deref()?).ok()?;
+            challenge_response::verify_response(&secret, &device_id, challenge, response).then_some(Role::Administrator)
+        });
+
+        AuditLog::write("support_unlock_attempt", &format!("device {} challenge {} {:?}", device_id, challenge, result));
+        result
+    }
+
+    /// Marks a verified unlock token as used; false if it was used before.
+    pub fn consume_unlock_token(&mut self, token: &UnlockToken, now: u64) -> bool {
+        self.state.used_unlock_tokens.retain(|used| used.expires > now);
+        if self.state.used_unlock_tokens.iter().any(|used| used.signature == token.signature) {
+            return false;
+        }
+        self.state.used_unlock_tokens.push(UsedUnlockToken { signature: token.signature.clone(), expires: token.expires });
+        self.save();
+        true
+    }
+
+    fn evaluate(&mut self, input: &str, config: &MainConfig, now: u64, check_credentials: impl FnOnce(&mut Self) -> Option<Role>) -> LoginResult {
+        if self.state.locked_until > now {
+            if config.recovery_code.as_deref().is_some_and(|code| !code.is_empty() && code == input) {
+                self.reset();
+                return LoginResult::RecoveryAccepted;
+            }
+            return LoginResult::LockedOut { seconds: self.state.locked_until - now };
+        }
+
+        if self.state.next_attempt_at > now {
+            return LoginResult::Throttled { seconds: self.state.next_attempt_at - now };
+        }
+
+        if let Some(role) = check_credentials(self) {
+            self.reset();
+            return LoginResult::Granted(role);
+        }
+
+        self.state.failed_attempts += 1;
+        let max_attempts = config.max_failed_attempts.max(1);
+        if self.state.failed_attempts >= max_attempts {
+            self.state.locked_until = now + config.lockout_minutes * 60;
+            self.state.next_attempt_at = 0;
+            self.state.failed_attempts = 0;
+            self.save();
+            return LoginResult::LockedOut { seconds: config.lockout_minutes * 60 };
+        }
+
+        // 1 s, 2 s, 4 s, ... after each further wrong password
+        self.state.next_attempt_at = now + (1u64 << (self.state.failed_attempts - 1).min(10));
+        self.save();
+        LoginResult::Denied { failed_attempts: self.state.failed_attempts, max_attempts }
+    }
+
+    /// Accepts the current TOTP code or consumes one of the recovery codes.
+    fn verify_second_factor(&mut self, code: &str, config: &MainConfig, now: u64) -> bool {
+        let Some(secret) = config.totp_secret.as_deref() else {
+            return true;
+        };
+
+        if let Some(step) = totp::verify(secret, code, config.totp_skew_steps, now) {
+            if step <= self.state.last_totp_step {
+                return false;
+            }
+            self.state.last_totp_step = step;
+            return true;
+        }
+
+        let code = code.trim();
+        if !code.is_empty() && config.totp_recovery_codes.iter().any(|recovery_code| recovery_code == code) {
+            let mut stored_config = ConfigManager::load_config(&"");
+            stored_config.totp_recovery_codes.retain(|recovery_code| recovery_code != code);
+            let _ = ConfigManager::save_config(&stored_config, &"");
+            AuditLog::write("totp_recovery_code_used", &format!("{} left", stored_config.totp_recovery_codes.len()));
+            return true;
+        }
+        false
+    }
+
+    fn reset(&mut self) {
+        self.state = LoginState { last_totp_step: self.state.last_totp_step, used_unlock_tokens: std::mem::take(&mut self.state.used_unlock_tokens), ..LoginState::default() };
+        self.save();
+    }
+
+    fn save(&self) {
+        let _ = confy::store_path(&self.path, &self.state);
+    }
+
+    fn get_state_file_path() -> PathBuf {
+        let mut file_path = ConfigManager::get_configuration_file_path(&"");
+        file_path.set_file_name("login_state.ron");
+        file_path
+    }
+}
+
+pub fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+    use crate::test_support::TempPath;
+
+    fn guard(path: &Path) -> LoginGuard {
+        LoginGuard { state: LoginState::default(), path: path.to_path_buf() }
+    }
+
+    fn config() -> MainConfig {
+        MainConfig { max_failed_attempts: 3, lockout_minutes: 15, recovery_code: Some("RECOVER".to_string()), ..MainConfig::default() }
+    }
+
+    #[test]
+    fn wrong_attempts_are_throttled_and_then_locked_out() {
+        let path = TempPath::new("lockout.ron");
+        let mut guard = guard(&path);
+        let config = config();
+        let now = 1_000_000;
+
+        assert_eq!(guard.evaluate("wrong", &config, now, |_| None), LoginResult::Denied { failed_attempts: 1, max_attempts: 3 });
+        assert_eq!(guard.evaluate("wrong", &config, now, |_| None), LoginResult::Throttled { seconds: 1 });
+        assert_eq!(guard.evaluate("wrong", &config, now + 1, |_| None), LoginResult::Denied { failed_attempts: 2, max_attempts: 3 });
+        assert_eq!(guard.evaluate("wrong", &config, now + 3, |_| None), LoginResult::LockedOut { seconds: 15 * 60 });
+
+        // Even the right password is refused while locked out.
+        assert_eq!(guard.evaluate("right", &config, now + 60, |_| Some(Role::Administrator)), LoginResult::LockedOut { seconds: 15 * 60 - 57 });
+    }
+
+    #[test]
+    fn lockout_expires() {
+        let path = TempPath::new("expiry.ron");
+        let mut guard = guard(&path);
+        let config = config();
+        let now = 1_000_000;
+        for offset in [0, 1, 3] {
+            guard.evaluate("wrong", &config, now + offset, |_| None);
+        }
+        assert!(matches!(guard.evaluate("right", &config, now + 3 + 15 * 60 - 1, |_| Some(Role::Administrator)), LoginResult::LockedOut { .. }));
+        assert_eq!(guard.evaluate("right", &config, now + 3 + 15 * 60, |_| Some(Role::Administrator)), LoginResult::Granted(Role::Administrator));
+    }
+
+    #[test]
+    fn recovery_code_clears_the_lockout() {
+        let path = TempPath::new("recovery.ron");
+        let mut guard = guard(&path);
+        let config = config();
+        let now = 1_000_000;
+        for offset in [0, 1, 3] {
+            guard.evaluate("wrong", &config, now + offset, |_| None);
+        }
+        assert_eq!(guard.evaluate("RECOVER", &config, now + 10, |_| None), LoginResult::RecoveryAccepted);
+        assert_eq!(guard.evaluate("right", &config, now + 10, |_| Some(Role::Operator)), LoginResult::Granted(Role::Operator));
+    }
+
+    #[test]
+    fn success_resets_the_failed_attempts() {
+        let path = TempPath::new("reset.ron");
+        let mut guard = guard(&path);
+        let config = config();
+        let now = 1_000_000;
+        guard.evaluate("wrong", &config, now, |_| None);
+        guard.evaluate("wrong", &config, now + 1, |_| None);
+        assert_eq!(guard.evaluate("right", &config, now + 3, |_| Some(Role::Administrator)), LoginResult::Granted(Role::Administrator));
+        assert_eq!(guard.evaluate("wrong", &config, now + 3, |_| None), LoginResult::Denied { failed_attempts: 1, max_attempts: 3 });
+    }
+
+    #[test]
+    fn unlock_token_works_once_until_it_expires() {
+        let path = TempPath::new("unlock_token.ron");
+        let mut guard = guard(&path);
+        let token = UnlockToken { device_id: "KIOSK".to_string(), expires: 2000, signature: "c2lnbmF0dXJl".to_string() };
+        assert!(guard.consume_unlock_token(&token, 1000));
+        assert!(!guard.consume_unlock_token(&token, 1500));
+
+        // Expired entries are dropped; the token itself is refused as expired before it gets here.
+        guard.consume_unlock_token(&UnlockToken { signature: "b3RoZXI=".to_string(), ..token }, 2500);
+        assert_eq!(guard.state.used_unlock_tokens, vec![UsedUnlockToken { signature: "b3RoZXI=".to_string(), expires: 2000 }]);
+    }
+
+    #[test]
+    fn limited_session_expires() {
+        let session = AdminSession::limited(Role::Administrator, Duration::ZERO);
+        assert!(!session.allows(Role::Operator));
+        assert!(AdminSession::new(Role::Operator).allows(Role::Operator));
+        assert!(!AdminSession::new(Role::Operator).allows(Role::Administrator));
+    }
+}
@@ -0,0 +1,158 @@
+use std::path::PathBuf;
+use winreg::{enums::HKEY_LOCAL_MACHINE, RegKey};
+
+
+/// Settings deployed by the fleet administrator (GPO/MDM) which the kiosk user cannot change.
+const POLICY_KEY_PATH: &str = r"SOFTWARE\Policies\winkioskshell";
+
+#[derive(Debug, Clone)]
+pub struct MachinePolicy {
+    /// Shared secret for support responses, DPAPI encrypted with --protect-secret
+    pub support_secret: Option<String>,
+    pub support_session_minutes: u64,
+    /// Base64 encoded ed25519 public key for USB unlock tokens
+    pub unlock_public_key: Option<String>,
+    pub usb_unlock_minutes: u64,
+    /// Base64 encoded minisign public key for release signatures, overrides the compiled in key
+    pub update_public_key: Option<String>,
+    /// "GitHub" (default), "Manifest" or "Directory"
+    pub update_source: Option<String>,
+    /// GitHub Enterprise API, manifest URL or local/UNC directory, depending on the source
+    pub update_url: Option<String>,
+    /// "owner/name" for the GitHub source
+    pub update_repository: Option<String>,
+    /// GitHub token for the releases API, DPAPI encrypted with --protect-secret
+    pub update_token: Option<String>,
+    /// Wildcard for the release asset, e.g. "winkioskshell-*-x64.zip"
+    pub update_asset_pattern: Option<String>,
+    /// Holds the kiosk on exactly this version, also downgrading to it
+    pub pinned_version: Option<String>,
+    /// Releases below are never installed and an older installed version is replaced without waiting for maintenance
+    pub minimum_version: Option<String>,
+    /// e.g. "http://proxy.corp.local:8080", used for every outgoing request
+    pub http_proxy: Option<String>,
+    /// Hosts reached without the proxy, e.g. "*.corp.local;10.0.0.0/8"
+    pub http_proxy_bypass: Option<String>,
+    pub http_proxy_user: Option<String>,
+    /// DPAPI encrypted with --protect-secret, never in plain text
+    pub http_proxy_password: Option<String>,
+    /// PEM or DER files of additional root certificates, e.g. for TLS inspection
+    pub http_root_certificates: Vec<PathBuf>,
+    /// Minisign public key for client application packages, the update key if not set
+    pub app_public_key: Option<String>,
+    /// Installs a client application package, "{file}" is replaced by its path, e.g. "msiexec /i {file} /qn"
+    pub app_install_command: Option<String>,
+    /// Folder of the client application; zip packages are extracted into it and it is backed up before every installation
+    pub app_extract_to: Option<PathBuf>,
+    /// Installs an update package from a USB drive on the update schedule instead of waiting for an administrator
+    pub usb_update_auto_install: bool,
+    /// SHA-256 of the bearer token of the local management API as hex; the API is off without one. Only the hash is kept
+    /// on the kiosk, the token itself stays with the management agent.
+    pub management_token_hash: Option<String>,
+    pub management_port: u16,
+    /// Commands the management API accepts besides the status, e.g. "restart-app", "reboot" or "maintenance/enter"
+    pub management_commands: Vec<String>,
+    pub http_connect_timeout_secs: u64,
+    /// Total time of an API or manifest request
+    pub http_timeout_secs: u64,
+    /// Downloads fail after this long without data, however long they take overall
+    pub http_read_timeout_secs: u64,
+}
+
+impl Default for MachinePolicy {
+    fn default() -> Self {
+        Self {
+            support_secret: None,
+            support_session_minutes: 30,
+            unlock_public_key: None,
+            usb_unlock_minutes: 30,
+            update_public_key: None,
+            update_source: None,
+            update_url: None,
+            update_repository: None,
+            update_token: None,
+            update_asset_pattern: None,
+            pinned_version: None,
+            minimum_version: None,
+            http_proxy: None,
+            http_proxy_bypass: None,
+            http_proxy_user: None,
+            http_proxy_password: None,
+            http_root_certificates: Vec::new(),
+            app_public_key: None,
+            app_install_command: None,
+            app_extract_to: None,
+            usb_update_auto_install: false,
+            management_token_hash: None,
+            management_port: 47900,
+            management_commands: vec!["restart-app".to_string(), "maintenance/leave".to_string(), "reload-config".to_string()],
+            http_connect_timeout_secs: 30,
+            http_timeout_secs: 300,
+            http_read_timeout_secs: 60,
+        }
+    }
+}
+
+impl MachinePolicy {
+    pub fn load() -> Self {
+        let mut policy = Self::default();
+        let Ok(key) = RegKey::predef(HKEY_LOCAL_MACHINE).open_subkey(POLICY_KEY_PATH) else {
+            return policy;
+        };
+
+        policy.support_secret = read_string(&key, "SupportSecret");
+        if let Some(minutes) = read_number(&key, "SupportSessionMinutes") {
+            policy.support_session_minutes = minutes;
+        }
+        policy.unlock_public_key = read_string(&key, "UnlockPublicKey");
+        if let Some(minutes) = read_number(&key, "UsbUnlockMinutes") {
+            policy.usb_unlock_minutes = minutes;
+        }
+        policy.update_public_key = read_string(&key, "UpdatePublicKey");
+        policy.update_source = read_string(&key, "UpdateSource");
+        policy.update_url = read_string(&key, "UpdateUrl");
+        policy.update_repository = read_string(&key, "UpdateRepository");
+        policy.update_token = read_string(&key, "UpdateToken");
+        policy.update_asset_pattern = read_string(&key, "UpdateAssetPattern");
+        policy.pinned_version = read_string(&key, "PinnedVersion");
+        policy.minimum_version = read_string(&key, "MinimumVersion");
+        policy.http_proxy = read_string(&key, "HttpProxy");
+        policy.http_proxy_bypass = read_string(&key, "HttpProxyBypass");
+        policy.http_proxy_user = read_string(&key, "HttpProxyUser");
+        policy.http_proxy_password = read_string(&key, "HttpProxyPassword");
+        if let Some(paths) = read_string(&key, "HttpRootCertificates") {
+            policy.http_root_certificates = paths.split(';').map(str::trim).filter(|path| !path.is_empty()).map(PathBuf::from).collect();
+        }
+        policy.app_public_key = read_string(&key, "AppPublicKey");
+        policy.app_install_command = read_string(&key, "AppInstallCommand");
+        policy.app_extract_to = read_string(&key, "AppExtractTo").map(PathBuf::from);
+        if let Some(auto_install) = read_number(&key, "UsbUpdateAutoInstall") {
+            policy.usb_update_auto_install = auto_install != 0;
+        }
+        policy.management_token_hash = read_string(&key, "ManagementTokenHash");
+        if let Some(port) = read_number(&key, "ManagementPort").and_then(|port| u16::try_from(port).ok()) {
+            policy.management_port = port;
+        }
+        if let Some(commands) = read_string(&key, "ManagementCommands") {
+            policy.management_commands = commands.split(';').map(str::trim).filter(|command| !command.is_empty()).map(str::to_string).collect();
+        }
+        if let Some(seconds) = read_number(&key, "HttpConnectTimeoutSecs") {
+            policy.http_connect_timeout_secs = seconds;
+        }
+        if let Some(seconds) = read_number(&key, "HttpTimeoutSecs") {
+            policy.http_timeout_secs = seconds;
+        }
+        if let Some(seconds) = read_number(&key, "HttpReadTimeoutSecs") {
+            policy.http_read_timeout_secs = seconds;
+        }
+        policy
+    }
+}
+
+fn read_string(key: &RegKey, name: &str) -> Option<String> {
+    key.get_value::<String, _>(name).ok().filter(|value| !value.is_empty())
+}
+
+fn read_number(key: &RegKey, name: &str) -> Option<u64> {
+    key.get_value::<u32, _>(name).ok().map(u64::from)
+}
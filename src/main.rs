@@ -5,13 +5,88 @@ use std::path::PathBuf;
 use mainconfig::ConfigManager;
 
 mod mainconfig;
+mod audit_log;
+mod login_guard;
+mod admin_gesture;
+mod totp;
+mod machinepolicy;
+mod device;
+mod challenge_response;
+mod removable_drives;
+mod unlock_token;
 mod win_kiosk_shell;
 mod win_kiosk_settings;
 mod win_elevation_functions;
+mod dpapi;
+mod http_client;
 mod release;
+mod update_source;
+mod update_cache;
+mod updater;
+mod maintenance_window;
+mod app_deployment;
+mod management_api;
+#[cfg(test)]
+mod test_support;
 
 
 fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() == 5 && args[1] == "--support-response" {
+        // Used by the support technician: winkioskshell.exe --support-response <secret file> <device id> <challenge>
+        // The secret is read from a file, so it does not show up in the process list.
+        let description = match std::fs::read_to_string(&args[2]) {
+            Ok(secret) => format!("Device {}, challenge {}:\n\n{}", args[3], args[4], challenge_response::compute_response(&secret, &args[3], &args[4])),
+            Err(e) => format!("Error: {}", e),
+        };
+        rfd::MessageDialog::new()
+            .set_title("Support response")
+            .set_description(description)
+            .show();
+        return;
+    }
+    if args.len() == 6 && args[1] == "--create-unlock-token" {
+        // winkioskshell.exe --create-unlock-token <private key> <device id> <valid hours> <output file>
+        let hours = args[4].parse::<u64>().unwrap_or(24);
+        let description = match unlock_token::UnlockToken::create(&args[2], &args[3], login_guard::unix_now() + hours * 3600) {
+            Ok(token) => match std::fs::write(&args[5], serde_json::to_string_pretty(&token).unwrap_or_default()) {
+                Ok(()) => format!("Unlock token for device {} written to {}", args[3], args[5]),
+                Err(e) => format!("Error: {}", e),
+            },
+            Err(e) => format!("Error: {}", e),
+        };
+        rfd::MessageDialog::new()
+            .set_title("Unlock token")
+            .set_description(description)
+            .show();
+        return;
+    }
+    if args.len() == 4 && args[1] == "--protect-secret" {
+        // Encrypts e.g. the proxy password for the machine policy: winkioskshell.exe --protect-secret <secret> <output file>
+        let description = match dpapi::protect(&args[2]).and_then(|protected| Ok(std::fs::write(&args[3], protected)?)) {
+            Ok(()) => format!("Encrypted secret written to {}", args[3]),
+            Err(e) => format!("Error: {}", e),
+        };
+        rfd::MessageDialog::new()
+            .set_title("Protect secret")
+            .set_description(description)
+            .show();
+        return;
+    }
+
+    if let Some(pid) = args.iter()
+        .position(|arg| arg == updater::WAIT_FOR_PID_ARGUMENT)
+        .and_then(|index| args.get(index + 1))
+        .and_then(|pid| pid.parse::<u32>().ok()) {
+        // Restarted after an update, the old instance is still shutting down.
+        updater::wait_for_exit(pid);
+    }
+
+    if updater::record_start() {
+        // The previous version was restored and started instead.
+        return;
+    }
+
     let config = ConfigManager::load_config(&"");
     let client_application = config.client_application;
     {
@@ -29,7 +104,8 @@ fn main() {
                 win_kiosk_shell::WinKioskShell::new(child).run();
             },
             None => {
-                win_kiosk_settings::WinKioskSettings::new().run();
+                // Without a client application the settings window is all there is to start.
+                win_kiosk_settings::WinKioskSettings::new().on_shown(updater::mark_healthy).run();
             }
         }
     }
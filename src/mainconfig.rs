@@ -2,11 +2,49 @@ use confy::ConfyError;
 use serde::{Serialize, Deserialize};
 use std::{env, path::{Path, PathBuf}};
 
+use crate::admin_gesture::AdminGesture;
+use crate::maintenance_window::MaintenanceWindow;
+use crate::release::UpdateChannel;
+
 
 #[derive(PartialEq, Debug, Serialize, Deserialize, Clone)]
+#[serde(default)]
 pub struct MainConfig {
     pub client_application: Option<String>,
     pub password: Option<String>,
+    pub operator_pin: Option<String>,
+    pub recovery_code: Option<String>,
+    pub max_failed_attempts: u32,
+    pub lockout_minutes: u64,
+    pub admin_gesture: AdminGesture,
+    pub admin_panel_timeout_secs: u64,
+    pub totp_secret: Option<String>,
+    pub totp_skew_steps: u64,
+    pub totp_recovery_codes: Vec<String>,
+    /// Staged updates are installed after this long without any input
+    pub update_idle_minutes: u64,
+    /// A new version which does not become healthy within this many starts is rolled back
+    pub update_max_boot_attempts: u32,
+    /// The client application has to run this long after an update to count as healthy
+    pub update_healthy_secs: u64,
+    pub update_channel: UpdateChannel,
+    pub update_check_hours: u64,
+    /// Bandwidth cap for update downloads on metered connections
+    pub download_limit_kb_per_sec: Option<u64>,
+    /// Manifest of the client application version to deploy
+    pub app_manifest_url: Option<String>,
+    /// Updates are installed when idle and, if there are any, inside one of these windows
+    pub maintenance_windows: Vec<MaintenanceWindow>,
+    /// A staged update is installed after this long even if the kiosk is never idle
+    pub update_max_deferral_hours: Option<u64>,
+    /// Warning shown before a forced installation
+    pub update_warning_secs: u64,
+    /// Serves verified updates to other kiosks on this port
+    pub update_cache_port: Option<u16>,
+    /// Kiosks asked for updates before the update source, e.g. "kiosk01:47800"
+    pub update_cache_peers: Vec<String>,
+    /// Announces the own update cache and finds the ones of other kiosks by mDNS
+    pub update_cache_discovery: bool,
 }
 
 impl Default for MainConfig {
@@ -14,6 +52,28 @@ impl Default for MainConfig {
         Self {
             client_application: None,
             password: None,
+            operator_pin: None,
+            recovery_code: None,
+            max_failed_attempts: 5,
+            lockout_minutes: 15,
+            admin_gesture: AdminGesture::default(),
+            admin_panel_timeout_secs: 60,
+            totp_secret: None,
+            totp_skew_steps: 1,
+            totp_recovery_codes: Vec::new(),
+            update_idle_minutes: 10,
+            update_max_boot_attempts: 3,
+            update_healthy_secs: 60,
+            update_channel: UpdateChannel::default(),
+            update_check_hours: 6,
+            download_limit_kb_per_sec: None,
+            app_manifest_url: None,
+            maintenance_windows: Vec::new(),
+            update_max_deferral_hours: None,
+            update_warning_secs: 300,
+            update_cache_port: None,
+            update_cache_peers: Vec::new(),
+            update_cache_discovery: false,
         }
     }
 }
@@ -50,10 +110,21 @@ impl ConfigManager {
     }
 
 
-    pub fn set_settings(user_name: &str, application: String, new_password: String) {
+    pub fn set_settings(user_name: &str, application: String, new_password: String, operator_pin: String, recovery_code: String) {
         let mut config = Self::load_config(user_name);
         config.client_application = Some(application);
         config.password = Some(new_password);
+        config.operator_pin = if operator_pin.is_empty() { None } else { Some(operator_pin) };
+        config.recovery_code = if recovery_code.is_empty() { None } else { Some(recovery_code) };
         let _ = Self::save_config(&config, user_name);
     }
-}
+
+
+    pub fn set_totp(user_name: &str, secret: Option<String>, recovery_codes: Vec<String>, skew_steps: u64) {
+        let mut config = Self::load_config(user_name);
+        config.totp_secret = secret;
+        config.totp_recovery_codes = recovery_codes;
+        config.totp_skew_steps = skew_steps;
+        let _ = Self::save_config(&config, user_name);
+    }
+}
\ No newline at end of file
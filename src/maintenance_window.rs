@@ -0,0 +1,95 @@
+use std::time::Duration;
+use chrono::{Local, NaiveTime};
+use serde::{Serialize, Deserialize};
+
+use crate::{mainconfig::MainConfig, updater};
+
+
+/// Daily span in local time for disruptive work, e.g. "02:00" to "04:00"; may cross midnight.
+#[derive(PartialEq, Debug, Serialize, Deserialize, Clone)]
+pub struct MaintenanceWindow {
+    pub start: String,
+    pub end: String,
+}
+
+impl MaintenanceWindow {
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        let (Ok(start), Ok(end)) = (NaiveTime::parse_from_str(&self.start, "%H:%M"), NaiveTime::parse_from_str(&self.end, "%H:%M")) else {
+            return false;
+        };
+        if start <= end {
+            start <= time && time < end
+        } else {
+            time >= start || time < end
+        }
+    }
+}
+
+/// Nobody touched the kiosk for `update_idle_minutes`, and it is inside one of the configured windows if there are any.
+pub fn is_maintenance_time(config: &MainConfig) -> bool {
+    is_maintenance_time_at(config, Local::now().time(), updater::idle_time())
+}
+
+fn is_maintenance_time_at(config: &MainConfig, now: NaiveTime, idle_time: Duration) -> bool {
+    // A visitor using the kiosk in the middle of the night must not lose it to an update either.
+    let idle = idle_time >= Duration::from_secs(config.update_idle_minutes * 60);
+    idle && (config.maintenance_windows.is_empty() || config.maintenance_windows.iter().any(|window| window.contains(now)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn window(start: &str, end: &str) -> MaintenanceWindow {
+        MaintenanceWindow { start: start.to_string(), end: end.to_string() }
+    }
+
+    fn time(time: &str) -> NaiveTime {
+        NaiveTime::parse_from_str(time, "%H:%M").unwrap()
+    }
+
+    #[test]
+    fn window_within_a_day() {
+        let window = window("02:00", "04:00");
+        assert!(window.contains(time("02:00")));
+        assert!(window.contains(time("03:59")));
+        assert!(!window.contains(time("04:00")));
+        assert!(!window.contains(time("01:59")));
+    }
+
+    #[test]
+    fn window_crossing_midnight() {
+        let window = window("22:00", "02:00");
+        assert!(window.contains(time("22:00")));
+        assert!(window.contains(time("23:59")));
+        assert!(window.contains(time("00:00")));
+        assert!(window.contains(time("01:59")));
+        assert!(!window.contains(time("02:00")));
+        assert!(!window.contains(time("12:00")));
+        assert!(!window.contains(time("21:59")));
+    }
+
+    #[test]
+    fn invalid_window_never_matches() {
+        assert!(!window("2am", "04:00").contains(time("03:00")));
+        assert!(!window("02:00", "").contains(time("03:00")));
+    }
+
+    #[test]
+    fn idle_is_required_inside_a_window() {
+        let config = MainConfig { update_idle_minutes: 10, maintenance_windows: vec![window("23:00", "01:00")], ..MainConfig::default() };
+        let idle = Duration::from_secs(10 * 60);
+        let busy = Duration::from_secs(60);
+
+        assert!(is_maintenance_time_at(&config, time("00:30"), idle));
+        assert!(!is_maintenance_time_at(&config, time("00:30"), busy));
+        assert!(!is_maintenance_time_at(&config, time("12:00"), idle));
+    }
+
+    #[test]
+    fn idle_is_enough_without_windows() {
+        let config = MainConfig { update_idle_minutes: 10, ..MainConfig::default() };
+        assert!(is_maintenance_time_at(&config, time("12:00"), Duration::from_secs(10 * 60)));
+        assert!(!is_maintenance_time_at(&config, time("12:00"), Duration::from_secs(9 * 60)));
+    }
+}
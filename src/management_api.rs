@@ -0,0 +1,203 @@
+use std::{error::Error, io::{self, Read}, sync::{mpsc::{self, Receiver, Sender}, Arc}, thread::{self, JoinHandle}, time::Duration};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use tiny_http::{Header, Method, Request, Response, Server};
+
+use crate::{audit_log::AuditLog, updater::LastCheck};
+
+
+/// How long a request waits for the shell to carry out its command before it is answered with "accepted".
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What a management tool can ask the shell to do.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ManagementCommand {
+    RestartApp,
+    Reboot,
+    EnterMaintenance,
+    LeaveMaintenance,
+    ReloadConfig,
+}
+
+impl ManagementCommand {
+    /// A command as named in the machine policy, the path with or without the leading slash, e.g. "maintenance/enter".
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::from_path(&format!("/{}", name.trim().trim_start_matches('/')))
+    }
+
+    fn from_path(path: &str) -> Option<Self> {
+        match path {
+            "/restart-app" => Some(Self::RestartApp),
+            "/reboot" => Some(Self::Reboot),
+            "/maintenance/enter" => Some(Self::EnterMaintenance),
+            "/maintenance/leave" => Some(Self::LeaveMaintenance),
+            "/reload-config" => Some(Self::ReloadConfig),
+            _ => None,
+        }
+    }
+}
+
+/// A command taken by the shell; the caller gets the result passed to `done`.
+pub struct PendingCommand {
+    pub command: ManagementCommand,
+    reply: Sender<Result<(), String>>,
+}
+
+impl PendingCommand {
+    pub fn done(self, result: Result<(), String>) {
+        let _ = self.reply.send(result);
+    }
+}
+
+/// Answer of GET /status.
+#[derive(Debug, Serialize)]
+pub struct KioskStatus {
+    pub version: String,
+    pub uptime_secs: u64,
+    pub app_state: String,
+    pub app_restarts: u32,
+    pub maintenance: bool,
+    pub staged_update: Option<String>,
+    pub last_update_check: Option<LastCheck>,
+}
+
+/// HTTP API on 127.0.0.1 for remote management tools running on the kiosk. Every request needs a bearer token whose
+/// SHA-256 is in the machine policy, and only the commands allowed there are accepted. Commands are queued for the shell,
+/// which picks them up with `take_command` and reports back whether they succeeded.
+pub struct ManagementApi {
+    server: Arc<Server>,
+    commands: Receiver<PendingCommand>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl ManagementApi {
+    pub fn start(port: u16, token_hash: String, allowed_commands: Vec<ManagementCommand>, status: impl Fn() -> KioskStatus + Send + 'static) -> Result<Self, Box<dyn Error>> {
+        let server = Arc::new(Server::http(("127.0.0.1", port)).map_err(|e| format!("management API on port {}: {}", port, e))?);
+        let (sender, commands) = mpsc::channel();
+        let worker = {
+            let server = server.clone();
+            thread::spawn(move || {
+                for request in server.incoming_requests() {
+                    let _ = handle(request, &token_hash, &allowed_commands, &status, &sender);
+                }
+            })
+        };
+        Ok(Self { server, commands, worker: Some(worker) })
+    }
+
+    pub fn take_command(&self) -> Option<PendingCommand> {
+        self.commands.try_recv().ok()
+    }
+}
+
+impl Drop for ManagementApi {
+    fn drop(&mut self) {
+        self.server.unblock();
+        // A request waiting for its command is answered right away instead of after the timeout.
+        while self.commands.try_recv().is_ok() {}
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+fn handle(mut request: Request, token_hash: &str, allowed_commands: &[ManagementCommand], status: &dyn Fn() -> KioskStatus, sender: &Sender<PendingCommand>) -> io::Result<()> {
+    let authorized = request.headers().iter()
+        .find(|header| header.field.equiv("Authorization"))
+        .and_then(|header| header.value.as_str().strip_prefix("Bearer "))
+        .is_some_and(|presented| token_matches(presented.trim(), token_hash));
+    if !authorized {
+        AuditLog::write("management_api_denied", &format!("{} {}", request.method(), request.url()));
+        return request.respond(json_response(401, &serde_json::json!({ "error": "unauthorized" })));
+    }
+
+    let path = request.url().split('?').next().unwrap_or_default().to_string();
+    match (request.method(), path.as_str()) {
+        (Method::Get, "/status") => {
+            let response = json_response(200, &status());
+            request.respond(response)
+        },
+        (Method::Post, path) => match ManagementCommand::from_path(path) {
+            Some(command) if !allowed_commands.contains(&command) => {
+                AuditLog::write("management_api_denied", &format!("{:?} is not allowed by the machine policy", command));
+                request.respond(json_response(403, &serde_json::json!({ "error": "command not allowed" })))
+            },
+            Some(command) => {
+                // The body is not used, but has to be read before answering.
+                let _ = io::copy(&mut request.as_reader().take(64 * 1024), &mut io::sink());
+                AuditLog::write("management_api_command", &format!("{:?}", command));
+                let (reply, result) = mpsc::channel();
+                let _ = sender.send(PendingCommand { command, reply });
+                match result.recv_timeout(COMMAND_TIMEOUT) {
+                    Ok(Ok(())) => request.respond(json_response(200, &serde_json::json!({ "done": format!("{:?}", command) }))),
+                    Ok(Err(e)) => request.respond(json_response(500, &serde_json::json!({ "error": e }))),
+                    Err(_) => request.respond(json_response(202, &serde_json::json!({ "accepted": format!("{:?}", command) }))),
+                }
+            },
+            None => request.respond(json_response(404, &serde_json::json!({ "error": "unknown command" }))),
+        },
+        _ => request.respond(json_response(404, &serde_json::json!({ "error": "not found" }))),
+    }
+}
+
+fn json_response(status: u16, body: &impl Serialize) -> Response<io::Cursor<Vec<u8>>> {
+    let content_type = Header::from_bytes("Content-Type", "application/json").unwrap();
+    Response::from_data(serde_json::to_vec(body).unwrap_or_default())
+        .with_status_code(status)
+        .with_header(content_type)
+}
+
+/// Compares the hash of the presented token in constant time, so the answer time tells nothing about the hash.
+fn token_matches(presented: &str, token_hash: &str) -> bool {
+    let presented_hash = format!("{:x}", Sha256::digest(presented.as_bytes()));
+    let token_hash = token_hash.trim().to_ascii_lowercase();
+    presented_hash.len() == token_hash.len()
+        && presented_hash.bytes().zip(token_hash.bytes()).fold(0u8, |difference, (a, b)| difference | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn token_is_compared_by_its_hash() {
+        // SHA-256 of "test"
+        let token_hash = "9F86D081884C7D659A2FEAA0C55AD015A3BF4F1B2B0B822CD15D6C15B0F00A08";
+        assert!(token_matches("test", token_hash));
+        assert!(!token_matches("Test", token_hash));
+        assert!(!token_matches("", token_hash));
+        assert!(!token_matches(token_hash, token_hash));
+    }
+
+    #[test]
+    fn commands_are_named_by_their_path() {
+        assert_eq!(ManagementCommand::from_name("maintenance/enter"), Some(ManagementCommand::EnterMaintenance));
+        assert_eq!(ManagementCommand::from_name(" /reboot"), Some(ManagementCommand::Reboot));
+        assert_eq!(ManagementCommand::from_name("status"), None);
+    }
+
+    #[test]
+    fn a_failed_command_is_reported_to_the_caller() {
+        let status = || KioskStatus {
+            version: String::new(), uptime_secs: 0, app_state: String::new(), app_restarts: 0, maintenance: false,
+            staged_update: None, last_update_check: None,
+        };
+        let token_hash = format!("{:x}", Sha256::digest(b"test"));
+        let api = ManagementApi::start(47913, token_hash, vec![ManagementCommand::RestartApp], status).unwrap();
+        let caller = thread::spawn(|| {
+            reqwest::blocking::Client::new().post("http://127.0.0.1:47913/restart-app").bearer_auth("test").send().unwrap()
+        });
+        let pending = loop {
+            if let Some(pending) = api.take_command() {
+                break pending;
+            }
+            thread::sleep(Duration::from_millis(10));
+        };
+        assert_eq!(pending.command, ManagementCommand::RestartApp);
+        pending.done(Err("client application not found".to_string()));
+
+        let response = caller.join().unwrap();
+        assert_eq!(response.status().as_u16(), 500);
+        assert_eq!(response.json::<serde_json::Value>().unwrap()["error"], "client application not found");
+    }
+}
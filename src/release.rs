@@ -1,60 +1,921 @@
-use std::{error::Error, fs::File, io::Write, path::Path};
+use std::{error::Error, fmt, fs::{self, File, OpenOptions}, io::{self, BufReader, Read, Write}, path::{Path, PathBuf}, thread, time::{Duration, Instant}};
+use chrono::{Local, TimeZone};
+use minisign_verify::{PublicKey, Signature};
+use wildmatch::WildMatch;
+use zip::ZipArchive;
 use semver::Version;
-use reqwest::blocking::Client;
-use serde::Deserialize;
+use serde::{Serialize, Deserialize};
+use sha2::{Digest, Sha256};
+
+use crate::{audit_log::AuditLog, machinepolicy::MachinePolicy, update_source::{Download, UpdateSource}};
+
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Why looking up or downloading a release failed.
+#[derive(Debug)]
+pub enum ReleaseError {
+    Http(reqwest::Error),
+    Io(io::Error),
+    /// An error status with the message from the response body
+    Status { status: u16, message: String },
+    /// The server limits the rate until this unix time
+    RateLimited { retry_at: u64 },
+    Version(semver::Error),
+    NotFound(String),
+    ChecksumMismatch { expected: String, actual: String },
+    Signature(minisign_verify::Error),
+    Archive(zip::result::ZipError),
+    Cancelled,
+    /// A response or file that cannot be used, e.g. a release list that is no JSON
+    Invalid(String),
+}
+
+impl fmt::Display for ReleaseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReleaseError::Http(e) => write!(f, "{}", e),
+            ReleaseError::Io(e) => write!(f, "{}", e),
+            ReleaseError::Status { status, message } if message.is_empty() => write!(f, "server answered {}", status),
+            ReleaseError::Status { status, message } => write!(f, "server answered {}: {}", status, message),
+            ReleaseError::RateLimited { retry_at } => match Local.timestamp_opt(*retry_at as i64, 0).single() {
+                Some(time) => write!(f, "rate limit reached, retrying after {}", time.format("%H:%M")),
+                None => write!(f, "rate limit reached"),
+            },
+            ReleaseError::Version(e) => write!(f, "invalid version: {}", e),
+            ReleaseError::NotFound(what) => write!(f, "{}", what),
+            ReleaseError::ChecksumMismatch { expected, actual } => write!(f, "checksum mismatch, expected {} but got {}", expected, actual),
+            ReleaseError::Signature(e) => write!(f, "invalid signature: {}", e),
+            ReleaseError::Archive(e) => write!(f, "{}", e),
+            ReleaseError::Cancelled => write!(f, "cancelled"),
+            ReleaseError::Invalid(what) => write!(f, "{}", what),
+        }
+    }
+}
+
+impl Error for ReleaseError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ReleaseError::Http(e) => Some(e),
+            ReleaseError::Io(e) => Some(e),
+            ReleaseError::Version(e) => Some(e),
+            ReleaseError::Signature(e) => Some(e),
+            ReleaseError::Archive(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for ReleaseError {
+    fn from(e: reqwest::Error) -> Self {
+        ReleaseError::Http(e)
+    }
+}
+
+impl From<io::Error> for ReleaseError {
+    fn from(e: io::Error) -> Self {
+        ReleaseError::Io(e)
+    }
+}
+
+impl From<semver::Error> for ReleaseError {
+    fn from(e: semver::Error) -> Self {
+        ReleaseError::Version(e)
+    }
+}
+
+impl From<minisign_verify::Error> for ReleaseError {
+    fn from(e: minisign_verify::Error) -> Self {
+        ReleaseError::Signature(e)
+    }
+}
+
+impl From<zip::result::ZipError> for ReleaseError {
+    fn from(e: zip::result::ZipError) -> Self {
+        ReleaseError::Archive(e)
+    }
+}
+
+/// Which releases a kiosk installs; pilot kiosks can run betas while the rest stay stable.
+#[derive(PartialEq, Debug, Serialize, Deserialize, Clone, Copy, Default)]
+pub enum UpdateChannel {
+    #[default]
+    Stable,
+    /// Also alpha, beta and rc prereleases
+    Beta,
+    /// Every release, including nightly builds
+    Nightly,
+}
+
+impl UpdateChannel {
+    fn accepts(&self, version: &Version, marked_prerelease: bool) -> bool {
+        let label = version.pre.as_str().split('.').next().unwrap_or_default().to_lowercase();
+        match self {
+            UpdateChannel::Stable => version.pre.is_empty() && !marked_prerelease,
+            UpdateChannel::Beta => label.is_empty() || ["alpha", "beta", "rc"].contains(&label.as_str()),
+            UpdateChannel::Nightly => true,
+        }
+    }
+}
 
 #[derive(Deserialize)]
 pub struct Release {
     tag_name: String,
     pub assets: Vec<Asset>,
+    /// Release notes in Markdown
+    #[serde(default)]
+    pub body: Option<String>,
+    #[serde(default)]
+    pub published_at: Option<String>,
+    #[serde(default)]
+    pub prerelease: bool,
+    #[serde(default)]
+    draft: bool,
 }
 
 #[derive(Deserialize)]
 pub struct Asset {
+    pub name: String,
     pub browser_download_url: String,
+    /// API URL of a GitHub asset, which also serves assets of private repositories
+    #[serde(default)]
+    pub url: Option<String>,
+    /// e.g. "sha256:2c26b4...", filled in by GitHub for newer releases
+    pub digest: Option<String>,
+    #[serde(default)]
+    pub content_type: Option<String>,
+}
+
+/// Architecture names used in asset names, the first one of each entry is the Rust name.
+const ARCHITECTURES: [&[&str]; 3] = [
+    &["x86_64", "x64", "amd64", "win64"],
+    &["aarch64", "arm64"],
+    &["x86", "i686", "win32"],
+];
+
+impl Asset {
+    pub fn is_zip(&self) -> bool {
+        self.name.to_lowercase().ends_with(".zip")
+            || self.content_type.as_deref().is_some_and(|content_type| content_type.contains("zip"))
+    }
+
+    fn is_executable(&self) -> bool {
+        self.name.to_lowercase().ends_with(".exe")
+            || self.content_type.as_deref().is_some_and(|content_type| content_type == "application/x-msdownload")
+    }
+
+    /// Some(true) for our architecture, Some(false) for another one and None if the name does not tell.
+    fn matches_architecture(&self) -> Option<bool> {
+        let name = self.name.to_lowercase();
+        let tokens: Vec<&str> = name.split(|c: char| !c.is_ascii_alphanumeric() && c != '_').collect();
+        let mentioned = ARCHITECTURES.iter()
+            .find(|names| names.iter().any(|arch| tokens.contains(arch)))?;
+        Some(mentioned[0] == std::env::consts::ARCH)
+    }
+}
+
+impl Release {
+    pub fn new(tag_name: String, assets: Vec<Asset>) -> Self {
+        Self { tag_name, assets, body: None, published_at: None, prerelease: false, draft: false }
+    }
+
+    pub fn version(&self) -> &str {
+        &self.tag_name
+    }
+
+    /// The detached minisign signature published next to the asset, e.g. "winkioskshell.exe.minisig".
+    pub fn signature_asset(&self, asset: &Asset) -> Option<&Asset> {
+        let signature_name = format!("{}.minisig", asset.name);
+        self.assets.iter().find(|candidate| candidate.name == signature_name)
+    }
+
+    /// A zstd patch from the running version to `asset`, e.g. "winkioskshell.exe.from-v1.4.0.zst",
+    /// created with `zstd --patch-from=<old exe> <new exe> -o <patch>`.
+    pub fn patch_asset(&self, asset: &Asset) -> Option<&Asset> {
+        let current_version = parse_version(env!("APP_VERSION")).ok()?;
+        let prefix = format!("{}.from-", asset.name);
+        self.assets.iter().find(|candidate| {
+            candidate.name.strip_prefix(&prefix)
+                .and_then(|rest| rest.strip_suffix(".zst"))
+                .is_some_and(|from_version| parse_version(from_version).is_ok_and(|from_version| from_version == current_version))
+        })
+    }
+
+    pub fn checksums_asset(&self) -> Option<&Asset> {
+        self.assets.iter().find(|candidate| candidate.name == "SHA256SUMS")
+    }
+
+    /// The executable or zip for this machine. Assets named for our architecture win over neutral ones
+    /// and a plain executable wins over a zip.
+    pub fn select_asset(&self, name_pattern: Option<&str>) -> Option<&Asset> {
+        let pattern = name_pattern.map(WildMatch::new);
+        self.assets.iter()
+            .filter(|asset| asset.is_executable() || asset.is_zip())
+            .filter(|asset| pattern.as_ref().map_or(true, |pattern| pattern.matches(&asset.name)))
+            .filter(|asset| asset.matches_architecture() != Some(false))
+            .max_by_key(|asset| (asset.matches_architecture().is_some(), asset.is_executable()))
+    }
+}
+
+/// The minisign public key from the machine policy, otherwise the one compiled into the binary.
+pub fn update_public_key() -> Option<String> {
+    MachinePolicy::load().update_public_key
+        .or_else(|| option_env!("UPDATE_PUBLIC_KEY").map(str::to_string))
+        .filter(|key| !key.trim().is_empty())
+}
+
+/// The highest release of the channel at or above the policy's minimum version; releases/latest would ignore every prerelease.
+/// A pinned version in the policy is taken regardless of the channel, but never below the minimum version.
+pub fn get_latest_release(source: &dyn UpdateSource, channel: UpdateChannel, policy: &MachinePolicy) -> Result<Release, ReleaseError> {
+    let pinned_version = policy.pinned_version.as_deref().map(parse_version).transpose()?;
+    let minimum_version = policy.minimum_version.as_deref().map(parse_version).transpose()?;
+    if let (Some(pinned_version), Some(minimum_version)) = (&pinned_version, &minimum_version) {
+        if pinned_version < minimum_version {
+            return Err(ReleaseError::Invalid(format!("pinned version {} is below the minimum version {}", pinned_version, minimum_version)));
+        }
+    }
+
+    let releases = source.releases()?.into_iter()
+        .filter(|release| !release.draft)
+        .filter_map(|release| parse_version(&release.tag_name).ok().map(|version| (version, release)));
+    if let Some(pinned_version) = pinned_version {
+        return releases
+            .into_iter()
+            .find(|(version, _)| *version == pinned_version)
+            .map(|(_, release)| release)
+            .ok_or_else(|| ReleaseError::NotFound(format!("pinned version {} not found", pinned_version)));
+    }
+
+    releases
+        .filter(|(version, release)| channel.accepts(version, release.prerelease))
+        .filter(|(version, _)| minimum_version.as_ref().map_or(true, |minimum_version| version >= minimum_version))
+        .max_by(|(a, _), (b, _)| a.cmp(b))
+        .map(|(_, release)| release)
+        .ok_or_else(|| ReleaseError::NotFound(format!("no {:?} release found", channel)))
+}
+
+/// Limits and progress of an asset download.
+pub struct DownloadOptions<'a> {
+    pub bytes_per_sec: Option<u64>,
+    /// Called with the bytes on disk and the total size, if known
+    pub on_progress: &'a dyn Fn(u64, Option<u64>),
+    /// Stops the download, keeping the partial file
+    pub is_cancelled: &'a dyn Fn() -> bool,
+}
+
+/// Downloads and verifies the asset; a file that fails verification is downloaded once more if the source has it elsewhere.
+fn download_latest_release(source: &dyn UpdateSource, release: &Release, asset: &Asset, public_key: &str, output_path: &Path, options: &DownloadOptions) -> Result<(), ReleaseError> {
+    match download_release(source, release, asset, public_key, output_path, options) {
+        Err(ReleaseError::ChecksumMismatch { .. } | ReleaseError::Signature(_)) if source.reject(&asset.browser_download_url) => {
+            download_release(source, release, asset, public_key, output_path, options)
+        },
+        result => result,
+    }
+}
+
+fn download_release(source: &dyn UpdateSource, release: &Release, asset: &Asset, public_key: &str, output_path: &Path, options: &DownloadOptions) -> Result<(), ReleaseError> {
+    let signature_asset = release.signature_asset(asset).ok_or_else(|| ReleaseError::NotFound(format!("{} has no signature", asset.name)))?;
+    let signature = download_text(source, &signature_asset.browser_download_url, options)?;
+    check_signed_version(&signature, release)?;
+    let expected_hash = expected_sha256(source, release, asset, options)?;
+
+    let url = &asset.browser_download_url;
+    download_verified(&|offset| source.open_at(url, offset), url, &expected_hash, &signature, public_key, output_path, options)
+}
+
+/// A patch against `base_path`, the running executable, saves most of the download; the full asset is the fallback.
+pub fn download_patched_or_full_release(source: &dyn UpdateSource, release: &Release, asset: &Asset, public_key: &str, base_path: Option<&Path>, output_path: &Path, options: &DownloadOptions) -> Result<(), ReleaseError> {
+    if let (Some(patch), Some(base_path)) = (release.patch_asset(asset).filter(|_| !asset.is_zip()), base_path) {
+        match download_patched_release(source, release, asset, patch, public_key, base_path, output_path, options) {
+            Ok(()) => return Ok(()),
+            Err(ReleaseError::Cancelled) => return Err(ReleaseError::Cancelled),
+            Err(e) => AuditLog::write("update_patch_failed", &format!("{}: {}", patch.name, e)),
+        }
+    }
+    download_latest_release(source, release, asset, public_key, output_path, options)
+}
+
+/// Rebuilds `asset` from its patch against `base_path`, the running executable. The patch is verified like a full download
+/// and the result against the checksum and signature of the full asset, so a wrong base never gets staged.
+fn download_patched_release(source: &dyn UpdateSource, release: &Release, asset: &Asset, patch: &Asset, public_key: &str, base_path: &Path, output_path: &Path, options: &DownloadOptions) -> Result<(), ReleaseError> {
+    let patch_signature_asset = release.signature_asset(patch).ok_or_else(|| ReleaseError::NotFound(format!("{} has no signature", patch.name)))?;
+    let patch_signature = download_text(source, &patch_signature_asset.browser_download_url, options)?;
+    let patch_hash = expected_sha256(source, release, patch, options)?;
+    let signature_asset = release.signature_asset(asset).ok_or_else(|| ReleaseError::NotFound(format!("{} has no signature", asset.name)))?;
+    let signature = download_text(source, &signature_asset.browser_download_url, options)?;
+    check_signed_version(&signature, release)?;
+    let expected_hash = expected_sha256(source, release, asset, options)?;
+
+    let mut patch_path = output_path.as_os_str().to_os_string();
+    patch_path.push(".zst");
+    let patch_path = PathBuf::from(patch_path);
+    let url = &patch.browser_download_url;
+    let result = download_verified(&|offset| source.open_at(url, offset), url, &patch_hash, &patch_signature, public_key, &patch_path, options)
+        .and_then(|()| apply_patch(base_path, &patch_path, &expected_hash, &signature, public_key, output_path));
+    let _ = fs::remove_file(&patch_path);
+    if let Err(ReleaseError::ChecksumMismatch { .. } | ReleaseError::Signature(_)) = &result {
+        // The caller falls back to the full asset, which should not come from the same place.
+        source.reject(url);
+    }
+    result
+}
+
+/// Decompresses the patch with the base file as reference and checks the result before it is moved to the output path.
+fn apply_patch(base_path: &Path, patch_path: &Path, expected_hash: &str, signature: &str, public_key: &str, output_path: &Path) -> Result<(), ReleaseError> {
+    let public_key = PublicKey::from_base64(public_key.trim())?;
+    let signature = Signature::decode(signature)?;
+    let mut signature_verifier = public_key.verify_stream(&signature)?;
+    let mut hasher = Sha256::new();
+
+    let base = fs::read(base_path)?;
+    let mut decoder = zstd::stream::read::Decoder::with_ref_prefix(BufReader::new(File::open(patch_path)?), &base)?;
+    // The reference covers the whole old executable, beyond the default window limit of 128 MB if need be.
+    decoder.window_log_max(31)?;
+
+    let part_path = partial_path(output_path);
+    let mut file = File::create(&part_path)?;
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        let count = match decoder.read(&mut buffer) {
+            Ok(count) => count,
+            Err(e) => {
+                let _ = fs::remove_file(&part_path);
+                return Err(e.into());
+            },
+        };
+        if count == 0 {
+            break;
+        }
+        hasher.update(&buffer[..count]);
+        signature_verifier.update(&buffer[..count]);
+        file.write_all(&buffer[..count])?;
+    }
+    file.flush()?;
+    drop(file);
+
+    let actual_hash = format!("{:x}", hasher.finalize());
+    if !actual_hash.eq_ignore_ascii_case(expected_hash) {
+        let _ = fs::remove_file(&part_path);
+        return Err(ReleaseError::ChecksumMismatch { expected: expected_hash.to_string(), actual: actual_hash });
+    }
+    if let Err(e) = signature_verifier.finalize() {
+        let _ = fs::remove_file(&part_path);
+        return Err(e.into());
+    }
+    fs::rename(&part_path, output_path)?;
+    Ok(())
+}
+
+/// A minisign signature covers the file but not the tag it is published under, so the release signatures carry
+/// "version:<tag>" in the trusted comment (`minisign -S -t "version:v1.5.0"`). Without the check an older signed build
+/// could be offered under a newer tag, e.g. in a renamed folder on a USB drive. The comment itself is covered by the
+/// signature, which is verified with the download.
+fn check_signed_version(signature: &str, release: &Release) -> Result<(), ReleaseError> {
+    let signed_version = Signature::decode(signature)?.trusted_comment()
+        .split_whitespace()
+        .find_map(|field| field.strip_prefix("version:"))
+        .map(str::to_string);
+    match signed_version {
+        Some(signed_version) if parse_version(&signed_version)? == parse_version(&release.tag_name)? => Ok(()),
+        signed_version => {
+            let message = format!("{} is signed for version {}", release.tag_name, signed_version.as_deref().unwrap_or("(none)"));
+            AuditLog::write("update_signature_invalid", &message);
+            Err(ReleaseError::Invalid(message))
+        },
+    }
+}
+
+/// Called before every request, a request already running is not interrupted.
+pub fn check_cancelled(is_cancelled: &dyn Fn() -> bool) -> Result<(), ReleaseError> {
+    if is_cancelled() {
+        return Err(ReleaseError::Cancelled);
+    }
+    Ok(())
+}
+
+/// Checks a detached minisign signature over data that is already in memory, e.g. a manifest.
+pub fn verify_signature(data: &[u8], signature: &str, public_key: &str) -> Result<(), ReleaseError> {
+    let public_key = PublicKey::from_base64(public_key.trim())?;
+    public_key.verify(data, &Signature::decode(signature)?, false)?;
+    Ok(())
+}
+
+/// Streams the asset into "<output>.part" while hashing it. An interrupted download is continued next time,
+/// a complete one is only moved to the output path when checksum and signature match.
+pub fn download_verified(open_at: &dyn Fn(u64) -> Result<Download, ReleaseError>, url: &str, expected_hash: &str, signature: &str, public_key: &str, output_path: &Path, options: &DownloadOptions) -> Result<(), ReleaseError> {
+    let public_key = PublicKey::from_base64(public_key.trim())?;
+    let signature = Signature::decode(signature)?;
+    let mut signature_verifier = public_key.verify_stream(&signature)?;
+    let mut hasher = Sha256::new();
+
+    let part_path = partial_path(output_path);
+    download_part(open_at, &part_path, options, &mut |chunk| {
+        hasher.update(chunk);
+        signature_verifier.update(chunk);
+    })?;
+
+    let actual_hash = format!("{:x}", hasher.finalize());
+    if !actual_hash.eq_ignore_ascii_case(expected_hash) {
+        let _ = fs::remove_file(&part_path);
+        AuditLog::write("update_download_rejected", &format!("{}: checksum mismatch, expected {} but got {}", url, expected_hash, actual_hash));
+        return Err(ReleaseError::ChecksumMismatch { expected: expected_hash.to_string(), actual: actual_hash });
+    }
+    if let Err(e) = signature_verifier.finalize() {
+        let _ = fs::remove_file(&part_path);
+        AuditLog::write("update_signature_invalid", &format!("{}: {}", url, e));
+        return Err(e.into());
+    }
+    fs::rename(&part_path, output_path)?;
+    Ok(())
 }
 
-pub fn get_latest_release(repo: &str) -> Result<Release, Box<dyn Error>> {
-    let url = format!("https://api.github.com/repos/{}/releases/latest", repo);
-    let client = Client::new();
-    let response = client.get(url)
-        .header("User-Agent", "request")
-        .send()?
-        .json::<Release>()?;
-    
-    Ok(response)
+/// Appends the rest of the file to the partial file; every byte of the file, old and new, is passed to `consume`.
+fn download_part(open_at: &dyn Fn(u64) -> Result<Download, ReleaseError>, part_path: &Path, options: &DownloadOptions, consume: &mut dyn FnMut(&[u8])) -> Result<(), ReleaseError> {
+    let existing = fs::metadata(part_path).map(|metadata| metadata.len()).unwrap_or(0);
+    check_cancelled(options.is_cancelled)?;
+    let mut download = open_at(existing)?;
+
+    let mut file = if download.offset > 0 {
+        let mut file = OpenOptions::new().read(true).append(true).open(part_path)?;
+        let mut buffer = vec![0u8; 64 * 1024];
+        loop {
+            let count = file.read(&mut buffer)?;
+            if count == 0 {
+                break;
+            }
+            consume(&buffer[..count]);
+        }
+        file
+    } else {
+        File::create(part_path)?
+    };
+
+    let bytes_per_sec = options.bytes_per_sec.filter(|limit| *limit > 0);
+    let buffer_size = bytes_per_sec.map_or(64 * 1024, |limit| limit.clamp(1024, 64 * 1024) as usize);
+    let mut buffer = vec![0u8; buffer_size];
+    let started_at = Instant::now();
+    let mut last_progress = started_at;
+    let mut received = 0u64;
+    loop {
+        check_cancelled(options.is_cancelled)?;
+        let count = download.reader.read(&mut buffer)?;
+        if count == 0 {
+            break;
+        }
+        consume(&buffer[..count]);
+        file.write_all(&buffer[..count])?;
+        received += count as u64;
+
+        if let Some(limit) = bytes_per_sec {
+            let due = Duration::from_secs_f64(received as f64 / limit as f64);
+            if let Some(wait) = due.checked_sub(started_at.elapsed()) {
+                thread::sleep(wait);
+            }
+        }
+        if last_progress.elapsed() >= PROGRESS_INTERVAL {
+            last_progress = Instant::now();
+            (options.on_progress)(download.offset + received, download.total);
+        }
+    }
+    file.flush()?;
+    (options.on_progress)(download.offset + received, download.total);
+    Ok(())
+}
+
+fn partial_path(output_path: &Path) -> PathBuf {
+    let mut file_name = output_path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".part");
+    output_path.with_file_name(file_name)
 }
 
-pub fn download_latest_release(url: &str, output_path: &Path) -> Result<(), Box<dyn Error>> {
-    let response = reqwest::blocking::get(url)?;
+/// Markdown release notes as plain text for a Text element: headings and emphasis stripped, bullets and links kept readable.
+pub fn release_notes_text(markdown: &str) -> String {
+    markdown.lines()
+        .map(|line| {
+            let line = line.trim_end();
+            let line = line.trim_start_matches('#').trim_start();
+            let line = match line.strip_prefix("- ").or_else(|| line.strip_prefix("* ")) {
+                Some(item) => format!("• {}", item),
+                None => line.to_string(),
+            };
+            strip_links(&line.replace("**", "").replace('`', ""))
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// "[text](url)" -> "text"
+fn strip_links(line: &str) -> String {
+    let mut result = String::new();
+    let mut rest = line;
+    while let Some(start) = rest.find('[') {
+        let Some((text, after)) = rest[start + 1..].split_once("](") else {
+            break;
+        };
+        let Some(end) = after.find(')') else {
+            break;
+        };
+        result.push_str(&rest[..start]);
+        result.push_str(text);
+        rest = &after[end + 1..];
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Unpacks the executable from a verified zip asset; prefers "winkioskshell.exe" if the archive has several.
+pub fn extract_executable(zip_path: &Path, output_path: &Path) -> Result<(), ReleaseError> {
+    let mut archive = ZipArchive::new(File::open(zip_path)?)?;
+    let executable_name = format!("{}.exe", env!("CARGO_PKG_NAME"));
+    let executables: Vec<String> = archive.file_names()
+        .filter(|name| name.to_lowercase().ends_with(".exe"))
+        .map(str::to_string)
+        .collect();
+    let entry_name = executables.iter()
+        .find(|name| Path::new(name).file_name().is_some_and(|file_name| file_name.eq_ignore_ascii_case(executable_name.as_str())))
+        .or(executables.first())
+        .ok_or_else(|| ReleaseError::Invalid("the zip contains no executable".to_string()))?
+        .clone();
+
+    let mut entry = archive.by_name(&entry_name)?;
     let mut file = File::create(output_path)?;
-    let content = response.bytes()?;
-    file.write_all(&content)?;
+    io::copy(&mut entry, &mut file)?;
+    file.flush()?;
     Ok(())
 }
 
-fn is_version_newer(version: &str) -> Result<bool, Box<dyn Error>> {
-    let trimmed_version = adjust_version(version);
-    let compare_version = Version::parse(&trimmed_version)?;
+/// The asset digest reported by the API, otherwise its line in the SHA256SUMS asset.
+fn expected_sha256(source: &dyn UpdateSource, release: &Release, asset: &Asset, options: &DownloadOptions) -> Result<String, ReleaseError> {
+    if let Some(digest) = asset.digest.as_deref().and_then(|digest| digest.strip_prefix("sha256:")) {
+        return Ok(digest.to_string());
+    }
+
+    let checksums_asset = release.checksums_asset().ok_or_else(|| ReleaseError::NotFound(format!("{} has no checksum", asset.name)))?;
+    let checksums = download_text(source, &checksums_asset.browser_download_url, options)?;
+    parse_checksum(&checksums, &asset.name).ok_or_else(|| ReleaseError::NotFound(format!("{} is missing in SHA256SUMS", asset.name)))
+}
+
+/// Finds "<hash>  <file name>" (or "<hash> *<file name>" for binary mode) in a sha256sum listing.
+fn parse_checksum(checksums: &str, file_name: &str) -> Option<String> {
+    checksums.lines().find_map(|line| {
+        let (hash, name) = line.trim().split_once(char::is_whitespace)?;
+        let name = name.trim_start().trim_start_matches('*');
+        (name == file_name && hash.len() == 64).then(|| hash.to_lowercase())
+    })
+}
+
+fn download_text(source: &dyn UpdateSource, url: &str, options: &DownloadOptions) -> Result<String, ReleaseError> {
+    check_cancelled(options.is_cancelled)?;
+    let mut text = String::new();
+    source.open(url)?.read_to_string(&mut text)?;
+    Ok(text)
+}
+
+fn is_version_newer(version: &str) -> Result<bool, ReleaseError> {
+    Ok(parse_version(version)? > parse_version(env!("APP_VERSION"))?)
+}
+
+/// A newer release, or with a pinned version any other release, so a pin below the installed version downgrades.
+pub fn is_update_available(release: &Release, policy: &MachinePolicy) -> Result<bool, ReleaseError> {
+    if policy.pinned_version.is_some() {
+        return Ok(parse_version(&release.tag_name)? != parse_version(env!("APP_VERSION"))?);
+    }
+    is_version_newer(&release.tag_name)
+}
 
-    let current_trimmed_version = adjust_version(env!("APP_VERSION"));
-    let current_version = Version::parse(&current_trimmed_version)?;
+/// The installed version is older than the policy allows, so an update must not wait for a maintenance window.
+pub fn is_below_minimum_version(policy: &MachinePolicy) -> bool {
+    let Some(minimum_version) = policy.minimum_version.as_deref().and_then(|version| parse_version(version).ok()) else {
+        return false;
+    };
+    parse_version(env!("APP_VERSION")).is_ok_and(|current_version| current_version < minimum_version)
+}
 
-    Ok(compare_version > current_version)
+/// Tags and the built version differ in form, e.g. "v1.5" and "1.5.0", so versions are compared parsed.
+pub fn is_same_version(version: &str, other_version: &str) -> bool {
+    matches!((parse_version(version), parse_version(other_version)), (Ok(version), Ok(other_version)) if version == other_version)
 }
 
-pub fn is_update_available(release: &Release) -> Result<bool, Box<dyn Error>> {
-    let is_newer = is_version_newer(&release.tag_name);
-    is_newer
+fn parse_version(version: &str) -> Result<Version, ReleaseError> {
+    Ok(Version::parse(&adjust_version(version))?)
 }
 
+/// "v1.4" -> "1.4.0", "v1.4-beta.2" -> "1.4.0-beta.2"; only the numeric part is padded.
 fn adjust_version(version: &str) -> String {
     let trimmed_version = version.trim_start_matches('v');
-    let dot_count = trimmed_version.matches('.').count();
+    let (core, suffix) = match trimmed_version.find(['-', '+']) {
+        Some(index) => trimmed_version.split_at(index),
+        None => (trimmed_version, ""),
+    };
+    let dot_count = core.matches('.').count();
 
     match dot_count {
-        1 => format!("{}.0", trimmed_version),
-        0 => format!("{}.0.0", trimmed_version),
+        1 => format!("{}.0{}", core, suffix),
+        0 => format!("{}.0.0{}", core, suffix),
         _ => trimmed_version.to_string(),
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use crate::test_support::{MinisignKey, TempPath};
+
+    struct FakeSource(Vec<(&'static str, bool)>);
+
+    impl UpdateSource for FakeSource {
+        fn releases(&self) -> Result<Vec<Release>, ReleaseError> {
+            Ok(self.0.iter()
+                .map(|(tag_name, prerelease)| Release { prerelease: *prerelease, ..Release::new(tag_name.to_string(), Vec::new()) })
+                .collect())
+        }
+
+        fn open_at(&self, location: &str, _offset: u64) -> Result<Download, ReleaseError> {
+            Err(ReleaseError::NotFound(location.to_string()))
+        }
+    }
+
+    fn latest(releases: Vec<(&'static str, bool)>, channel: UpdateChannel) -> String {
+        get_latest_release(&FakeSource(releases), channel, &MachinePolicy::default()).unwrap().version().to_string()
+    }
+
+    fn asset(name: &str) -> Asset {
+        Asset { name: name.to_string(), browser_download_url: format!("https://example.com/{}", name), url: None, digest: None, content_type: None }
+    }
+
+    #[test]
+    fn stable_channel_skips_prereleases() {
+        let releases = vec![("v1.0.0", false), ("v1.1.0-beta.1", false), ("v1.0.1", false), ("v1.2.0", true)];
+        assert_eq!(latest(releases, UpdateChannel::Stable), "v1.0.1");
+    }
+
+    #[test]
+    fn beta_channel_orders_prereleases_by_semver() {
+        let releases = vec![("v1.0.0", false), ("v1.1.0-alpha.1", true), ("v1.1.0-beta.2", true), ("v1.1.0-beta.10", true), ("v1.1.0-nightly.5", true)];
+        assert_eq!(latest(releases.clone(), UpdateChannel::Beta), "v1.1.0-beta.10");
+
+        let mut with_rc = releases.clone();
+        with_rc.push(("v1.1.0-rc.1", true));
+        assert_eq!(latest(with_rc.clone(), UpdateChannel::Beta), "v1.1.0-rc.1");
+
+        // The final release ranks above all of its prereleases.
+        with_rc.push(("v1.1", false));
+        assert_eq!(latest(with_rc, UpdateChannel::Beta), "v1.1");
+    }
+
+    #[test]
+    fn nightly_channel_takes_every_release() {
+        let releases = vec![("v1.1.0-beta.10", true), ("v1.1.0-nightly.5", true), ("v1.0.0", false)];
+        assert_eq!(latest(releases, UpdateChannel::Nightly), "v1.1.0-nightly.5");
+    }
+
+    #[test]
+    fn drafts_and_unparsable_tags_are_ignored() {
+        struct DraftSource;
+        impl UpdateSource for DraftSource {
+            fn releases(&self) -> Result<Vec<Release>, ReleaseError> {
+                Ok(vec![
+                    Release::new("v1.0.0".to_string(), Vec::new()),
+                    Release { draft: true, ..Release::new("v2.0.0".to_string(), Vec::new()) },
+                    Release::new("latest".to_string(), Vec::new()),
+                ])
+            }
+            fn open_at(&self, location: &str, _offset: u64) -> Result<Download, ReleaseError> {
+                Err(ReleaseError::NotFound(location.to_string()))
+            }
+        }
+        assert_eq!(get_latest_release(&DraftSource, UpdateChannel::Nightly, &MachinePolicy::default()).unwrap().version(), "v1.0.0");
+    }
+
+    #[test]
+    fn pinned_version_wins_over_the_channel() {
+        let policy = MachinePolicy { pinned_version: Some("1.1.0-beta.1".to_string()), ..MachinePolicy::default() };
+        let releases = FakeSource(vec![("v1.0.0", false), ("v1.1.0-beta.1", true), ("v1.2.0", false)]);
+        assert_eq!(get_latest_release(&releases, UpdateChannel::Stable, &policy).unwrap().version(), "v1.1.0-beta.1");
+
+        let policy = MachinePolicy { pinned_version: Some("v0.9".to_string()), ..MachinePolicy::default() };
+        assert!(matches!(get_latest_release(&releases, UpdateChannel::Stable, &policy), Err(ReleaseError::NotFound(_))));
+    }
+
+    #[test]
+    fn minimum_version_filters_releases() {
+        let policy = MachinePolicy { minimum_version: Some("v1.1".to_string()), ..MachinePolicy::default() };
+        let releases = FakeSource(vec![("v1.0.0", false), ("v1.1.0-beta.1", true)]);
+        assert!(matches!(get_latest_release(&releases, UpdateChannel::Beta, &policy), Err(ReleaseError::NotFound(_))));
+
+        let releases = FakeSource(vec![("v1.0.0", false), ("v1.1.0", false)]);
+        assert_eq!(get_latest_release(&releases, UpdateChannel::Stable, &policy).unwrap().version(), "v1.1.0");
+    }
+
+    #[test]
+    fn pinned_version_below_minimum_is_refused() {
+        let policy = MachinePolicy { pinned_version: Some("v1.0.0".to_string()), minimum_version: Some("v1.1.0".to_string()), ..MachinePolicy::default() };
+        let releases = FakeSource(vec![("v1.0.0", false), ("v1.1.0", false)]);
+        assert!(matches!(get_latest_release(&releases, UpdateChannel::Stable, &policy), Err(ReleaseError::Invalid(_))));
+    }
+
+    const KEY: u8 = 3;
+
+    /// Serves files by asset name. After `reject` the files of `upstream` are served instead, like a peer source does.
+    struct FileSource {
+        files: HashMap<String, Vec<u8>>,
+        upstream: Option<HashMap<String, Vec<u8>>>,
+        rejected: std::cell::Cell<bool>,
+        opened: std::cell::RefCell<Vec<String>>,
+    }
+
+    impl FileSource {
+        fn new(files: HashMap<String, Vec<u8>>, upstream: Option<HashMap<String, Vec<u8>>>) -> Self {
+            Self { files, upstream, rejected: std::cell::Cell::new(false), opened: std::cell::RefCell::new(Vec::new()) }
+        }
+    }
+
+    impl UpdateSource for FileSource {
+        fn releases(&self) -> Result<Vec<Release>, ReleaseError> {
+            Ok(Vec::new())
+        }
+        fn open_at(&self, location: &str, _offset: u64) -> Result<Download, ReleaseError> {
+            let name = location.rsplit('/').next().unwrap_or_default();
+            self.opened.borrow_mut().push(name.to_string());
+            let files = self.upstream.as_ref().filter(|_| self.rejected.get()).unwrap_or(&self.files);
+            let content = files.get(name).cloned().ok_or_else(|| ReleaseError::NotFound(name.to_string()))?;
+            Ok(Download { total: Some(content.len() as u64), reader: Box::new(io::Cursor::new(content)), offset: 0 })
+        }
+        fn reject(&self, _location: &str) -> bool {
+            self.upstream.is_some() && !self.rejected.replace(true)
+        }
+    }
+
+    /// A release `tag` with the files, their checksums and signatures whose trusted comment names `signed_version`.
+    fn signed_release(tag: &str, signed_version: &str, files: &[(&str, &[u8])]) -> (Release, HashMap<String, Vec<u8>>) {
+        let key = MinisignKey::new(KEY);
+        let mut assets = Vec::new();
+        let mut contents = HashMap::new();
+        for (name, data) in files {
+            assets.push(Asset { digest: Some(format!("sha256:{:x}", Sha256::digest(data))), ..asset(name) });
+            assets.push(asset(&format!("{}.minisig", name)));
+            contents.insert(name.to_string(), data.to_vec());
+            contents.insert(format!("{}.minisig", name), key.sign(data, &format!("timestamp:1700000000\tversion:{}", signed_version)).into_bytes());
+        }
+        (Release::new(tag.to_string(), assets), contents)
+    }
+
+    fn download_options() -> DownloadOptions<'static> {
+        DownloadOptions { bytes_per_sec: None, on_progress: &|_, _| {}, is_cancelled: &|| false }
+    }
+
+    #[test]
+    fn a_rejected_download_is_fetched_once_more_if_the_source_has_it_elsewhere() {
+        let (release, files) = signed_release("v1.0.0", "v1.0.0", &[("winkioskshell.exe", b"new build")]);
+        let mut tampered = files.clone();
+        tampered.insert("winkioskshell.exe".to_string(), b"tampered".to_vec());
+        let public_key = MinisignKey::new(KEY).public_key();
+        let output_path = TempPath::new("rejected_download.exe");
+        let download = |source: &FileSource| download_latest_release(source, &release, &release.assets[0], &public_key, &output_path, &download_options());
+
+        assert!(matches!(download(&FileSource::new(tampered.clone(), None)), Err(ReleaseError::ChecksumMismatch { .. })));
+        assert!(!output_path.exists());
+
+        download(&FileSource::new(tampered, Some(files))).unwrap();
+        assert_eq!(fs::read(&output_path).unwrap(), b"new build");
+    }
+
+    #[test]
+    fn an_older_signed_build_is_refused_under_a_newer_tag() {
+        let public_key = MinisignKey::new(KEY).public_key();
+        let output_path = TempPath::new("renamed_release.exe");
+
+        let (release, files) = signed_release("v99.0.0", "v1.0.0", &[("winkioskshell.exe", b"old build")]);
+        let source = FileSource::new(files, None);
+        assert!(matches!(download_latest_release(&source, &release, &release.assets[0], &public_key, &output_path, &download_options()), Err(ReleaseError::Invalid(_))));
+        assert_eq!(*source.opened.borrow(), ["winkioskshell.exe.minisig"]);
+
+        let (release, mut files) = signed_release("v1.0.0", "v1.0.0", &[("winkioskshell.exe", b"old build")]);
+        let unversioned = MinisignKey::new(KEY).sign(b"old build", "timestamp:1700000000\tfile:winkioskshell.exe");
+        files.insert("winkioskshell.exe.minisig".to_string(), unversioned.into_bytes());
+        assert!(matches!(download_latest_release(&FileSource::new(files, None), &release, &release.assets[0], &public_key, &output_path, &download_options()), Err(ReleaseError::Invalid(_))));
+
+        let (release, files) = signed_release("v1.0", "v1.0.0", &[("winkioskshell.exe", b"old build")]);
+        download_latest_release(&FileSource::new(files, None), &release, &release.assets[0], &public_key, &output_path, &download_options()).unwrap();
+    }
+
+    /// A running build and a new one that differs in a few places, like two versions of the shell.
+    fn builds() -> (Vec<u8>, Vec<u8>) {
+        let base: Vec<u8> = (0..200_000u32).map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8).collect();
+        let mut new = base.clone();
+        new[1_000..1_010].copy_from_slice(b"new build!");
+        new.extend_from_slice(b"appended code");
+        (base, new)
+    }
+
+    /// Like `zstd --patch-from=<base> <new>`.
+    fn make_patch(base: &[u8], new: &[u8]) -> Vec<u8> {
+        let mut encoder = zstd::stream::write::Encoder::with_ref_prefix(Vec::new(), 3, base).unwrap();
+        encoder.write_all(new).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn patch_name() -> String {
+        format!("winkioskshell.exe.from-{}.zst", env!("APP_VERSION"))
+    }
+
+    #[test]
+    fn a_patch_rebuilds_the_release_from_the_running_version() {
+        let (base, new) = builds();
+        let patch = make_patch(&base, &new);
+        assert!(patch.len() < new.len() / 10);
+        let (release, files) = signed_release("v9.0.0", "v9.0.0", &[("winkioskshell.exe", &new), (&patch_name(), &patch)]);
+        let base_path = TempPath::new("patch_base.exe");
+        fs::write(&base_path, &base).unwrap();
+        let output_path = TempPath::new("patched.exe");
+        let source = FileSource::new(files, None);
+
+        download_patched_or_full_release(&source, &release, &release.assets[0], &MinisignKey::new(KEY).public_key(), Some(&base_path), &output_path, &download_options()).unwrap();
+        assert_eq!(fs::read(&output_path).unwrap(), new);
+        assert!(!source.opened.borrow().contains(&"winkioskshell.exe".to_string()));
+    }
+
+    #[test]
+    fn a_patch_against_another_build_falls_back_to_the_full_download() {
+        let (base, new) = builds();
+        let (release, files) = signed_release("v9.0.0", "v9.0.0", &[("winkioskshell.exe", &new), (&patch_name(), &make_patch(&base, &new))]);
+        // Not the build the patch was made from, e.g. a kiosk that runs a hotfix.
+        let base_path = TempPath::new("other_base.exe");
+        fs::write(&base_path, &base[..150_000]).unwrap();
+        let output_path = TempPath::new("mismatched_patch.exe");
+        let source = FileSource::new(files, None);
+
+        download_patched_or_full_release(&source, &release, &release.assets[0], &MinisignKey::new(KEY).public_key(), Some(&base_path), &output_path, &download_options()).unwrap();
+        assert_eq!(fs::read(&output_path).unwrap(), new);
+        assert!(source.opened.borrow().contains(&"winkioskshell.exe".to_string()));
+    }
+
+    #[test]
+    fn a_corrupt_patch_falls_back_to_the_full_download() {
+        let (base, new) = builds();
+        let (release, mut files) = signed_release("v9.0.0", "v9.0.0", &[("winkioskshell.exe", &new), (&patch_name(), &make_patch(&base, &new))]);
+        let corrupt = files.get_mut(&patch_name()).unwrap();
+        let middle = corrupt.len() / 2;
+        corrupt[middle] ^= 0xff;
+        let base_path = TempPath::new("corrupt_patch_base.exe");
+        fs::write(&base_path, &base).unwrap();
+        let output_path = TempPath::new("corrupt_patch.exe");
+        let source = FileSource::new(files, None);
+
+        download_patched_or_full_release(&source, &release, &release.assets[0], &MinisignKey::new(KEY).public_key(), Some(&base_path), &output_path, &download_options()).unwrap();
+        assert_eq!(fs::read(&output_path).unwrap(), new);
+        assert!(source.opened.borrow().contains(&"winkioskshell.exe".to_string()));
+    }
+
+    #[test]
+    fn tags_and_built_versions_compare_by_semver() {
+        assert!(is_same_version("v1.5.0", "1.5.0"));
+        assert!(is_same_version("v1.5", "1.5.0"));
+        assert!(is_same_version("v1.5.0-beta.2", "1.5.0-beta.2"));
+        assert!(!is_same_version("v1.5.1", "1.5.0"));
+        assert!(!is_same_version("v1.5.0-beta.2", "1.5.0"));
+        assert!(!is_same_version("latest", "latest"));
+    }
+
+    #[test]
+    fn parse_checksum_finds_the_file() {
+        let hash = "2C26B46B68FFC68FF99B453C1D30413413422D706483BFA0F98A5E886266E7AE";
+        let checksums = format!("{}  LICENSE\n{} *winkioskshell.exe\nabc  short.exe\n", "0".repeat(64), hash);
+        assert_eq!(parse_checksum(&checksums, "winkioskshell.exe"), Some(hash.to_lowercase()));
+        assert_eq!(parse_checksum(&checksums, "LICENSE"), Some("0".repeat(64)));
+        assert_eq!(parse_checksum(&checksums, "short.exe"), None);
+        assert_eq!(parse_checksum(&checksums, "missing.exe"), None);
+    }
+
+    #[test]
+    fn select_asset_prefers_own_architecture_and_executables() {
+        let own = match std::env::consts::ARCH { "aarch64" => "arm64", "x86" => "win32", _ => "x64" };
+        let foreign = if own == "arm64" { "x64" } else { "arm64" };
+
+        let release = Release::new("v1.0.0".to_string(), vec![
+            asset("winkioskshell.exe"),
+            asset(&format!("winkioskshell-{}.zip", own)),
+            asset(&format!("winkioskshell-{}.exe", foreign)),
+            asset("winkioskshell.exe.minisig"),
+            asset("SHA256SUMS"),
+        ]);
+        assert_eq!(release.select_asset(None).unwrap().name, format!("winkioskshell-{}.zip", own));
+        assert_eq!(release.select_asset(Some("winkioskshell.exe")).unwrap().name, "winkioskshell.exe");
+        assert!(release.select_asset(Some(&format!("*-{}.exe", foreign))).is_none());
+
+        let release = Release::new("v1.0.0".to_string(), vec![
+            asset(&format!("winkioskshell-{}.zip", own)),
+            asset(&format!("winkioskshell-{}.exe", own)),
+        ]);
+        assert_eq!(release.select_asset(None).unwrap().name, format!("winkioskshell-{}.exe", own));
+        assert_eq!(release.select_asset(Some("*.zip")).unwrap().name, format!("winkioskshell-{}.zip", own));
+    }
+
+    #[test]
+    fn adjust_version_pads_the_numeric_part() {
+        assert_eq!(adjust_version("v1.4"), "1.4.0");
+        assert_eq!(adjust_version("v1.4-beta.2"), "1.4.0-beta.2");
+        assert_eq!(adjust_version("2"), "2.0.0");
+        assert_eq!(adjust_version("v1.2.3+build"), "1.2.3+build");
+    }
+}
@@ -0,0 +1,59 @@
+use std::{collections::HashSet, path::PathBuf, sync::{Arc, atomic::{AtomicBool, Ordering}}, thread::{self, JoinHandle}, time::Duration};
+use winapi::um::fileapi::{GetDriveTypeW, GetLogicalDrives};
+use winapi::um::winbase::DRIVE_REMOVABLE;
+
+
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+pub fn list_removable_drives() -> Vec<PathBuf> {
+    let mask = unsafe { GetLogicalDrives() };
+
+    (0..26u8)
+        .filter(|index| mask & (1 << index) != 0)
+        .map(|index| format!("{}:\\", (b'A' + index) as char))
+        .filter(|root| {
+            let wide: Vec<u16> = root.encode_utf16().chain(std::iter::once(0)).collect();
+            unsafe { GetDriveTypeW(wide.as_ptr()) == DRIVE_REMOVABLE }
+        })
+        .map(PathBuf::from)
+        .collect()
+}
+
+/// Polls for newly inserted USB drives and reports the root of each one once. Drives already present at startup are
+/// not reported, so a stick left in the kiosk does not act again after every reboot.
+pub struct DriveWatcher {
+    running: Arc<AtomicBool>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl DriveWatcher {
+    pub fn spawn(on_inserted: impl Fn(PathBuf) + Send + 'static) -> Self {
+        let running = Arc::new(AtomicBool::new(true));
+
+        let running_clone = running.clone();
+        let worker = thread::spawn(move || {
+            let mut known_drives: HashSet<PathBuf> = list_removable_drives().into_iter().collect();
+
+            while running_clone.load(Ordering::SeqCst) {
+                let drives: HashSet<PathBuf> = list_removable_drives().into_iter().collect();
+                for drive in drives.difference(&known_drives) {
+                    on_inserted(drive.clone());
+                }
+                known_drives = drives;
+
+                thread::sleep(POLL_INTERVAL);
+            }
+        });
+
+        Self { running, worker: Some(worker) }
+    }
+}
+
+impl Drop for DriveWatcher {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
@@ -0,0 +1,161 @@
+use std::{fs, ops::Deref, path::{Path, PathBuf}};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use ed25519_dalek::{Signer, SigningKey};
+
+
+/// A file or folder in the temp directory, unique to the test process and removed when dropped.
+pub struct TempPath(PathBuf);
+
+impl TempPath {
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("winkioskshell_test_{}_{}", std::process::id(), name));
+        remove(&path);
+        Self(path)
+    }
+}
+
+impl Deref for TempPath {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempPath {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        remove(&self.0);
+    }
+}
+
+fn remove(path: &Path) {
+    let _ = fs::remove_dir_all(path).or_else(|_| fs::remove_file(path));
+}
+
+/// Creates minisign signatures like `minisign -S -t <trusted comment>`, so tests can sign whatever they download.
+pub struct MinisignKey {
+    signing_key: SigningKey,
+    key_id: [u8; 8],
+}
+
+impl MinisignKey {
+    pub fn new(seed: u8) -> Self {
+        Self { signing_key: SigningKey::from_bytes(&[seed; 32]), key_id: [seed; 8] }
+    }
+
+    pub fn public_key(&self) -> String {
+        STANDARD.encode([b"Ed".as_slice(), &self.key_id, &self.signing_key.verifying_key().to_bytes()].concat())
+    }
+
+    pub fn sign(&self, data: &[u8], trusted_comment: &str) -> String {
+        let signature = self.signing_key.sign(&blake2b_512(data)).to_bytes();
+        let global_signature = self.signing_key.sign(&[signature.as_slice(), trusted_comment.as_bytes()].concat()).to_bytes();
+        format!(
+            "untrusted comment: test key\n{}\ntrusted comment: {}\n{}\n",
+            STANDARD.encode([b"ED".as_slice(), &self.key_id, &signature].concat()),
+            trusted_comment,
+            STANDARD.encode(global_signature),
+        )
+    }
+}
+
+const BLAKE2B_IV: [u64; 8] = [
+    0x6a09e667f3bcc908, 0xbb67ae8584caa73b, 0x3c6ef372fe94f82b, 0xa54ff53a5f1d36f1,
+    0x510e527fade682d1, 0x9b05688c2b3e6c1f, 0x1f83d9abfb41bd6b, 0x5be0cd19137e2179,
+];
+
+const BLAKE2B_SIGMA: [[usize; 16]; 10] = [
+    [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15],
+    [14, 10, 4, 8, 9, 15, 13, 6, 1, 12, 0, 2, 11, 7, 5, 3],
+    [11, 8, 12, 0, 5, 2, 15, 13, 10, 14, 3, 6, 7, 1, 9, 4],
+    [7, 9, 3, 1, 13, 12, 11, 14, 2, 6, 5, 10, 4, 0, 15, 8],
+    [9, 0, 5, 7, 2, 4, 10, 15, 14, 1, 11, 12, 6, 8, 3, 13],
+    [2, 12, 6, 10, 0, 11, 8, 3, 4, 13, 7, 5, 15, 14, 1, 9],
+    [12, 5, 1, 15, 14, 13, 4, 10, 0, 7, 6, 3, 9, 2, 8, 11],
+    [13, 11, 7, 14, 12, 1, 3, 9, 5, 0, 15, 4, 8, 6, 2, 10],
+    [6, 15, 14, 9, 11, 3, 0, 8, 12, 2, 13, 7, 1, 4, 10, 5],
+    [10, 2, 8, 4, 7, 6, 1, 5, 15, 11, 9, 14, 3, 12, 13, 0],
+];
+
+/// BLAKE2b-512 (RFC 7693), the prehash of minisign; no crate of the build offers it.
+fn blake2b_512(data: &[u8]) -> [u8; 64] {
+    let mut state = BLAKE2B_IV;
+    state[0] ^= 0x0101_0000 ^ 64;
+
+    let block_count = data.len().div_ceil(128).max(1);
+    for (index, block) in (0..block_count).map(|index| (index, &data[index * 128..data.len().min((index + 1) * 128)])) {
+        let mut buffer = [0u8; 128];
+        buffer[..block.len()].copy_from_slice(block);
+        let counter = (index * 128 + block.len()) as u128;
+        blake2b_compress(&mut state, &buffer, counter, index + 1 == block_count);
+    }
+
+    let mut digest = [0u8; 64];
+    for (chunk, word) in digest.chunks_exact_mut(8).zip(state) {
+        chunk.copy_from_slice(&word.to_le_bytes());
+    }
+    digest
+}
+
+fn blake2b_compress(state: &mut [u64; 8], block: &[u8; 128], counter: u128, last: bool) {
+    let mut message = [0u64; 16];
+    for (word, chunk) in message.iter_mut().zip(block.chunks_exact(8)) {
+        *word = u64::from_le_bytes(chunk.try_into().unwrap());
+    }
+    let mut v = [0u64; 16];
+    v[..8].copy_from_slice(state);
+    v[8..].copy_from_slice(&BLAKE2B_IV);
+    v[12] ^= counter as u64;
+    v[13] ^= (counter >> 64) as u64;
+    if last {
+        v[14] = !v[14];
+    }
+
+    for round in 0..12 {
+        let s = &BLAKE2B_SIGMA[round % 10];
+        for (i, [a, b, c, d]) in [[0, 4, 8, 12], [1, 5, 9, 13], [2, 6, 10, 14], [3, 7, 11, 15], [0, 5, 10, 15], [1, 6, 11, 12], [2, 7, 8, 13], [3, 4, 9, 14]].into_iter().enumerate() {
+            let (x, y) = (message[s[2 * i]], message[s[2 * i + 1]]);
+            v[a] = v[a].wrapping_add(v[b]).wrapping_add(x);
+            v[d] = (v[d] ^ v[a]).rotate_right(32);
+            v[c] = v[c].wrapping_add(v[d]);
+            v[b] = (v[b] ^ v[c]).rotate_right(24);
+            v[a] = v[a].wrapping_add(v[b]).wrapping_add(y);
+            v[d] = (v[d] ^ v[a]).rotate_right(16);
+            v[c] = v[c].wrapping_add(v[d]);
+            v[b] = (v[b] ^ v[c]).rotate_right(63);
+        }
+    }
+    for (i, word) in state.iter_mut().enumerate() {
+        *word ^= v[i] ^ v[i + 8];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use minisign_verify::{PublicKey, Signature};
+
+    #[test]
+    fn blake2b_matches_the_rfc_example() {
+        let digest: String = blake2b_512(b"abc").iter().map(|byte| format!("{:02x}", byte)).collect();
+        assert_eq!(digest, "ba80a53f981c4d0d6a2797b69f12f6e94c212f14685ac4b74b12bb6fdbffa2d1\
+            7d87c5392aab792dc252d5de4533cc9518d38aa8dbf1925ab92386edd4009923");
+    }
+
+    #[test]
+    fn signatures_verify_with_minisign() {
+        let key = MinisignKey::new(7);
+        let public_key = PublicKey::from_base64(&key.public_key()).unwrap();
+        let data = vec![0x5a; 300];
+        let signature = Signature::decode(&key.sign(&data, "version:v1.0.0")).unwrap();
+        assert_eq!(signature.trusted_comment(), "version:v1.0.0");
+        assert!(public_key.verify(&data, &signature, false).is_ok());
+        assert!(public_key.verify(&data[1..], &signature, false).is_err());
+    }
+}
@@ -0,0 +1,100 @@
+use data_encoding::BASE32_NOPAD;
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use sha1::Sha1;
+
+
+const TIME_STEP_SECS: u64 = 30;
+const CODE_DIGITS: u32 = 6;
+const RECOVERY_CODE_COUNT: usize = 8;
+
+/// Random 160 bit secret in the base32 form authenticator apps expect.
+pub fn generate_secret() -> String {
+    let mut secret = [0u8; 20];
+    rand::thread_rng().fill(&mut secret);
+    BASE32_NOPAD.encode(&secret)
+}
+
+pub fn provisioning_uri(secret: &str, account: &str) -> String {
+    let issuer = env!("CARGO_PKG_NAME");
+    format!("otpauth://totp/{}:{}?secret={}&issuer={}&digits={}&period={}", issuer, account, secret, issuer, CODE_DIGITS, TIME_STEP_SECS)
+}
+
+pub fn generate_recovery_codes() -> Vec<String> {
+    let mut rng = rand::thread_rng();
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| format!("{:05}-{:05}", rng.gen_range(0..100_000), rng.gen_range(0..100_000)))
+        .collect()
+}
+
+/// Returns the time step of the matching code, so the caller can reject replays.
+pub fn verify(secret: &str, code: &str, skew_steps: u64, unix_time: u64) -> Option<u64> {
+    let key = BASE32_NOPAD.decode(secret.trim().to_uppercase().as_bytes()).ok()?;
+    let code = code.trim();
+    if code.len() != CODE_DIGITS as usize {
+        return None;
+    }
+
+    // Every step is compared, so the answer time does not tell which one matched.
+    let current_step = unix_time / TIME_STEP_SECS;
+    (current_step.saturating_sub(skew_steps)..=current_step + skew_steps)
+        .filter(|step| codes_match(&generate_code(&key, *step), code))
+        .last()
+}
+
+/// Compares in constant time, like the management API token.
+fn codes_match(expected: &str, presented: &str) -> bool {
+    expected.len() == presented.len()
+        && expected.bytes().zip(presented.bytes()).fold(0u8, |difference, (a, b)| difference | (a ^ b)) == 0
+}
+
+fn generate_code(key: &[u8], step: u64) -> String {
+    let mut mac = Hmac::<Sha1>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(&step.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+
+    // RFC 4226 dynamic truncation
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([hash[offset] & 0x7f, hash[offset + 1], hash[offset + 2], hash[offset + 3]]);
+    format!("{:0width$}", binary % 10u32.pow(CODE_DIGITS), width = CODE_DIGITS as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// base32 of the RFC 6238 SHA1 test secret "12345678901234567890"
+    const RFC_SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+    #[test]
+    fn rfc6238_test_vectors() {
+        // The last six digits of the eight digit codes from RFC 6238, appendix B.
+        for (time, code) in [(59, "287082"), (1111111109, "081804"), (1111111111, "050471"), (1234567890, "005924"), (2000000000, "279037"), (20000000000, "353130")] {
+            assert_eq!(verify(RFC_SECRET, code, 0, time), Some(time / TIME_STEP_SECS), "time {}", time);
+        }
+    }
+
+    #[test]
+    fn skew_accepts_neighbouring_steps_only() {
+        assert_eq!(verify(RFC_SECRET, "287082", 1, 59 + 30), Some(1));
+        assert_eq!(verify(RFC_SECRET, "287082", 0, 59 + 30), None);
+        assert_eq!(verify(RFC_SECRET, "287082", 1, 59 + 60), None);
+    }
+
+    #[test]
+    fn rejects_wrong_and_malformed_codes() {
+        assert_eq!(verify(RFC_SECRET, "287083", 1, 59), None);
+        assert_eq!(verify(RFC_SECRET, "28708", 1, 59), None);
+        assert_eq!(verify(RFC_SECRET, "", 1, 59), None);
+        assert_eq!(verify("not base32!", "287082", 1, 59), None);
+        assert_eq!(verify(&RFC_SECRET.to_lowercase(), " 287082 ", 0, 59), Some(1));
+    }
+
+    #[test]
+    fn generated_secret_verifies() {
+        let secret = generate_secret();
+        let code = generate_code(&BASE32_NOPAD.decode(secret.as_bytes()).unwrap(), 1000);
+        assert_eq!(verify(&secret, &code, 0, 1000 * TIME_STEP_SECS), Some(1000));
+        assert!(provisioning_uri(&secret, "kiosk").contains(&format!("secret={}", secret)));
+    }
+}
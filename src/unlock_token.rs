@@ -0,0 +1,102 @@
+use std::{error::Error, fs, path::Path};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use serde::{Serialize, Deserialize};
+
+
+pub const UNLOCK_TOKEN_FILE_NAME: &str = "winkioskshell-unlock.json";
+
+/// Emergency unlock file on a USB drive, signed with the key whose public half is in the machine policy.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UnlockToken {
+    pub device_id: String,
+    /// Unix time in seconds
+    pub expires: u64,
+    pub signature: String,
+}
+
+impl UnlockToken {
+    pub fn read(drive: &Path) -> Option<Self> {
+        let content = fs::read_to_string(drive.join(UNLOCK_TOKEN_FILE_NAME)).ok()?;
+        serde_json::from_str(&content).ok()
+    }
+
+    pub fn create(private_key: &str, device_id: &str, expires: u64) -> Result<Self, Box<dyn Error>> {
+        let key_bytes: [u8; 32] = STANDARD.decode(private_key.trim())?.try_into().map_err(|_| "private key must be 32 bytes")?;
+        let signing_key = SigningKey::from_bytes(&key_bytes);
+        let signature = signing_key.sign(signed_message(device_id, expires).as_bytes());
+
+        Ok(Self { device_id: device_id.to_string(), expires, signature: STANDARD.encode(signature.to_bytes()) })
+    }
+
+    pub fn verify(&self, public_key: &str, device_id: &str, now: u64) -> Result<(), Box<dyn Error>> {
+        if !self.device_id.eq_ignore_ascii_case(device_id) {
+            return Err(format!("token is bound to device {}", self.device_id).into());
+        }
+        if self.expires <= now {
+            return Err("token expired".into());
+        }
+
+        let key_bytes: [u8; 32] = STANDARD.decode(public_key.trim())?.try_into().map_err(|_| "public key must be 32 bytes")?;
+        let signature_bytes: [u8; 64] = STANDARD.decode(self.signature.trim())?.try_into().map_err(|_| "signature must be 64 bytes")?;
+        let verifying_key = VerifyingKey::from_bytes(&key_bytes)?;
+        verifying_key.verify(signed_message(&self.device_id, self.expires).as_bytes(), &Signature::from_bytes(&signature_bytes))?;
+        Ok(())
+    }
+}
+
+fn signed_message(device_id: &str, expires: u64) -> String {
+    format!("winkioskshell-unlock\n{}\n{}", device_id.to_uppercase(), expires)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PRIVATE_KEY: [u8; 32] = [42; 32];
+
+    fn keys() -> (String, String) {
+        let public_key = SigningKey::from_bytes(&PRIVATE_KEY).verifying_key();
+        (STANDARD.encode(PRIVATE_KEY), STANDARD.encode(public_key.to_bytes()))
+    }
+
+    #[test]
+    fn valid_token_verifies() {
+        let (private_key, public_key) = keys();
+        let token = UnlockToken::create(&private_key, "kiosk-01", 2000).unwrap();
+        assert!(token.verify(&public_key, "KIOSK-01", 1999).is_ok());
+    }
+
+    #[test]
+    fn expired_token_is_rejected() {
+        let (private_key, public_key) = keys();
+        let token = UnlockToken::create(&private_key, "kiosk-01", 2000).unwrap();
+        assert!(token.verify(&public_key, "kiosk-01", 2000).is_err());
+    }
+
+    #[test]
+    fn token_for_another_device_is_rejected() {
+        let (private_key, public_key) = keys();
+        let token = UnlockToken::create(&private_key, "kiosk-01", 2000).unwrap();
+        assert!(token.verify(&public_key, "kiosk-02", 1000).is_err());
+
+        // Changing the device in the file breaks the signature.
+        let token = UnlockToken { device_id: "kiosk-02".to_string(), ..token };
+        assert!(token.verify(&public_key, "kiosk-02", 1000).is_err());
+    }
+
+    #[test]
+    fn badly_signed_token_is_rejected() {
+        let (private_key, public_key) = keys();
+        let token = UnlockToken::create(&private_key, "kiosk-01", 2000).unwrap();
+
+        let extended = UnlockToken { expires: 3000, signature: token.signature.clone(), device_id: token.device_id.clone() };
+        assert!(extended.verify(&public_key, "kiosk-01", 1000).is_err());
+
+        let other_key = STANDARD.encode(SigningKey::from_bytes(&[1; 32]).verifying_key().to_bytes());
+        assert!(token.verify(&other_key, "kiosk-01", 1000).is_err());
+
+        let garbage = UnlockToken { signature: "bm90IGEgc2lnbmF0dXJl".to_string(), ..token };
+        assert!(garbage.verify(&public_key, "kiosk-01", 1000).is_err());
+    }
+}
@@ -0,0 +1,241 @@
+use std::{collections::{HashMap, HashSet}, error::Error, fs::{self, File}, io::{self, Read, Seek, SeekFrom}, path::{Component, Path, PathBuf}, sync::{Arc, Mutex}, thread::{self, JoinHandle}, time::{Duration, Instant}};
+use lazy_static::lazy_static;
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+use reqwest::blocking::Client;
+use tiny_http::{Header, Method, Response, Server, StatusCode};
+
+use crate::{audit_log::AuditLog, http_client, machinepolicy::MachinePolicy, mainconfig::{ConfigManager, MainConfig}, release::{Asset, Release, ReleaseError}, update_source::{self, Download, UpdateSource}};
+
+
+const SERVICE_TYPE: &str = "_winkioskshell._tcp.local.";
+const DISCOVERY_TIME: Duration = Duration::from_secs(2);
+/// A peer that does not answer quickly is skipped; the LAN is either fast or the peer is gone.
+const PEER_CONNECT_TIMEOUT: Duration = Duration::from_secs(3);
+
+lazy_static! {
+    /// Peers that served a file which failed verification; they are not asked again until the shell restarts.
+    static ref REJECTED_PEERS: Mutex<HashSet<String>> = Mutex::new(HashSet::new());
+}
+
+/// Tries the update cache of other kiosks before the configured source. Peers only serve the assets, which are verified
+/// against the signature and checksums from the source, so a peer can save bandwidth but never change what gets installed.
+pub struct PeerSource {
+    upstream: Box<dyn UpdateSource>,
+    peers: Vec<String>,
+    client: Client,
+    /// Asset location of the upstream source -> "<tag>/<asset name>" on a peer
+    locations: Mutex<HashMap<String, String>>,
+    /// Asset location -> the peer it was last opened from
+    served_by: Mutex<HashMap<String, String>>,
+}
+
+/// The source with the configured and discovered peers in front, the source itself if there are none.
+pub fn with_peers(upstream: Box<dyn UpdateSource>, config: &MainConfig, policy: &MachinePolicy) -> Result<Box<dyn UpdateSource>, Box<dyn Error>> {
+    let mut peers = config.update_cache_peers.clone();
+    if config.update_cache_discovery {
+        for peer in discover_peers() {
+            if !peers.contains(&peer) {
+                peers.push(peer);
+            }
+        }
+    }
+    let rejected_peers = REJECTED_PEERS.lock().unwrap();
+    peers.retain(|peer| !rejected_peers.contains(peer));
+    if peers.is_empty() {
+        return Ok(upstream);
+    }
+    let client = http_client::peer_client(policy, PEER_CONNECT_TIMEOUT)?;
+    Ok(Box::new(PeerSource { upstream, peers, client, locations: Mutex::new(HashMap::new()), served_by: Mutex::new(HashMap::new()) }))
+}
+
+impl UpdateSource for PeerSource {
+    fn releases(&self) -> Result<Vec<Release>, ReleaseError> {
+        let releases = self.upstream.releases()?;
+        let mut locations = self.locations.lock().unwrap();
+        for release in &releases {
+            // A minisign signature does not name the version it signs, so with signatures from a peer an older signed
+            // build could be passed off under the new tag. Signatures and checksums always come from the source.
+            let checksums = release.checksums_asset().map(|asset| &asset.name);
+            for asset in release.assets.iter().filter(|asset| !asset.name.ends_with(".minisig") && Some(&asset.name) != checksums) {
+                locations.insert(asset.browser_download_url.clone(), format!("{}/{}", release.version(), asset.name));
+            }
+        }
+        Ok(releases)
+    }
+
+    fn open_at(&self, location: &str, offset: u64) -> Result<Download, ReleaseError> {
+        self.served_by.lock().unwrap().remove(location);
+        if let Some(path) = self.locations.lock().unwrap().get(location).cloned() {
+            for peer in &self.peers {
+                if REJECTED_PEERS.lock().unwrap().contains(peer) {
+                    continue;
+                }
+                if let Ok(download) = update_source::http_get_at(&self.client, &format!("http://{}/{}", peer, path), offset) {
+                    self.served_by.lock().unwrap().insert(location.to_string(), peer.clone());
+                    return Ok(download);
+                }
+            }
+        }
+        self.upstream.open_at(location, offset)
+    }
+
+    /// A patch and the full asset may come from different peers, so every peer that served one of them is dropped.
+    fn reject(&self, _location: &str) -> bool {
+        let peers: HashSet<String> = self.served_by.lock().unwrap().drain().map(|(_, peer)| peer).collect();
+        for peer in &peers {
+            AuditLog::write("update_peer_rejected", peer);
+        }
+        REJECTED_PEERS.lock().unwrap().extend(peers.iter().cloned());
+        !peers.is_empty()
+    }
+}
+
+/// Copies a verified asset into the cache served to peers; only the latest release is kept.
+pub fn store(release: &Release, asset: &Asset, path: &Path) -> Result<(), Box<dyn Error>> {
+    let cache_directory = get_cache_directory();
+    let release_directory = cache_directory.join(release.version());
+    if let Ok(entries) = fs::read_dir(&cache_directory) {
+        for entry in entries.flatten().filter(|entry| entry.path() != release_directory) {
+            let _ = fs::remove_dir_all(entry.path()).or_else(|_| fs::remove_file(entry.path()));
+        }
+    }
+    fs::create_dir_all(&release_directory)?;
+
+    store_file(&cache_directory, &release_directory, &asset.name, &mut File::open(path)?)?;
+    AuditLog::write("update_cached", &format!("{}/{}", release.version(), asset.name));
+    Ok(())
+}
+
+/// Writes next to the release folder first, where nothing is served, so peers never get a half written file.
+fn store_file(cache_directory: &Path, release_directory: &Path, name: &str, reader: &mut dyn Read) -> io::Result<()> {
+    let temporary_path = cache_directory.join(format!("{}.tmp", name));
+    let result = File::create(&temporary_path)
+        .and_then(|mut file| io::copy(reader, &mut file).and_then(|_| file.sync_all()))
+        .and_then(|()| fs::rename(&temporary_path, release_directory.join(name)));
+    if result.is_err() {
+        let _ = fs::remove_file(&temporary_path);
+    }
+    result
+}
+
+/// Serves the update cache over HTTP and announces it by mDNS; stops when dropped.
+pub struct UpdateCacheServer {
+    server: Arc<Server>,
+    mdns: Option<ServiceDaemon>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl UpdateCacheServer {
+    pub fn start(port: u16, announce: bool) -> Result<Self, Box<dyn Error>> {
+        let server = Arc::new(Server::http(("0.0.0.0", port)).map_err(|e| format!("update cache on port {}: {}", port, e))?);
+        let worker = {
+            let server = server.clone();
+            thread::spawn(move || {
+                let cache_directory = get_cache_directory();
+                for request in server.incoming_requests() {
+                    let _ = serve(request, &cache_directory);
+                }
+            })
+        };
+        let mdns = if announce { Some(announce_service(port)?) } else { None };
+        Ok(Self { server, mdns, worker: Some(worker) })
+    }
+}
+
+impl Drop for UpdateCacheServer {
+    fn drop(&mut self) {
+        if let Some(mdns) = self.mdns.take() {
+            let _ = mdns.shutdown();
+        }
+        self.server.unblock();
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+/// GET "/<tag>/<file>" with an optional "Range: bytes=<offset>-", so peers can continue interrupted downloads.
+fn serve(request: tiny_http::Request, cache_directory: &Path) -> io::Result<()> {
+    if *request.method() != Method::Get {
+        return request.respond(Response::empty(405));
+    }
+    let Some(path) = cache_path(cache_directory, request.url()) else {
+        return request.respond(Response::empty(404));
+    };
+    let Ok(mut file) = File::open(&path) else {
+        return request.respond(Response::empty(404));
+    };
+    let total = file.metadata()?.len();
+    let offset = request.headers().iter()
+        .find(|header| header.field.equiv("Range"))
+        .and_then(|header| header.value.as_str().strip_prefix("bytes=")?.strip_suffix('-')?.parse::<u64>().ok());
+
+    match offset {
+        Some(offset) if offset >= total => request.respond(Response::empty(416)),
+        Some(offset) => {
+            file.seek(SeekFrom::Start(offset))?;
+            let content_range = Header::from_bytes("Content-Range", format!("bytes {}-{}/{}", offset, total - 1, total)).unwrap();
+            request.respond(Response::new(StatusCode(206), vec![content_range], file, Some((total - offset) as usize), None))
+        },
+        None => request.respond(Response::from_file(file)),
+    }
+}
+
+/// Only plain file names below a release folder of the cache, never anything else on the disk.
+fn cache_path(cache_directory: &Path, url: &str) -> Option<PathBuf> {
+    let relative = Path::new(url.split('?').next()?.trim_start_matches('/'));
+    let components: Vec<Component> = relative.components().collect();
+    if components.len() != 2 || !components.iter().all(|component| matches!(component, Component::Normal(name) if !name.to_string_lossy().contains(':'))) {
+        return None;
+    }
+    Some(cache_directory.join(relative))
+}
+
+fn get_host_name() -> String {
+    whoami::fallible::hostname().unwrap_or_else(|_| "kiosk".to_string())
+}
+
+fn announce_service(port: u16) -> Result<ServiceDaemon, Box<dyn Error>> {
+    let host_name = get_host_name();
+    let daemon = ServiceDaemon::new()?;
+    let service = ServiceInfo::new(SERVICE_TYPE, &host_name, &format!("{}.local.", host_name), "", port, None::<HashMap<String, String>>)?
+        .enable_addr_auto();
+    daemon.register(service)?;
+    Ok(daemon)
+}
+
+/// "address:port" of every other kiosk announcing an update cache, as far as they answer within a moment.
+fn discover_peers() -> Vec<String> {
+    let Ok(daemon) = ServiceDaemon::new() else {
+        return Vec::new();
+    };
+    let Ok(receiver) = daemon.browse(SERVICE_TYPE) else {
+        let _ = daemon.shutdown();
+        return Vec::new();
+    };
+
+    let own_host = format!("{}.local.", get_host_name());
+    let mut peers = Vec::new();
+    let deadline = Instant::now() + DISCOVERY_TIME;
+    while let Some(timeout) = deadline.checked_duration_since(Instant::now()) {
+        match receiver.recv_timeout(timeout) {
+            // A kiosk that serves the cache and discovers peers finds its own announcement, which has no newer files.
+            Ok(ServiceEvent::ServiceResolved(info)) if info.get_hostname().eq_ignore_ascii_case(&own_host) => {},
+            Ok(ServiceEvent::ServiceResolved(info)) => {
+                if let Some(address) = info.get_addresses().iter().find(|address| address.is_ipv4()) {
+                    peers.push(format!("{}:{}", address, info.get_port()));
+                }
+            },
+            Ok(_) => {},
+            Err(_) => break,
+        }
+    }
+    let _ = daemon.shutdown();
+    peers
+}
+
+fn get_cache_directory() -> PathBuf {
+    let mut directory = ConfigManager::get_configuration_file_path(&"");
+    directory.set_file_name("update_cache");
+    directory
+}
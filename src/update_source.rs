@@ -0,0 +1,564 @@
+use std::{error::Error, fs::{self, File}, io::{self, Read, Seek, SeekFrom}, path::{Path, PathBuf}};
+use reqwest::{blocking::{Client, RequestBuilder, Response}, header::{ACCEPT, ETAG, IF_NONE_MATCH, RANGE, RETRY_AFTER}, StatusCode, Url};
+use serde::{Serialize, Deserialize};
+
+use crate::{dpapi, http_client, login_guard::unix_now, machinepolicy::MachinePolicy, mainconfig::ConfigManager, release::{Asset, Release, ReleaseError}};
+
+
+const DEFAULT_GITHUB_API: &str = "https://api.github.com";
+const DEFAULT_REPOSITORY: &str = "audioprog/winkioskshell";
+/// Backoff when a server limits the rate without telling until when.
+const DEFAULT_RETRY_SECS: u64 = 15 * 60;
+
+/// Where releases are listed and downloaded from.
+pub trait UpdateSource {
+    /// Every release the source offers, in any order; the channel filter is applied by the caller.
+    fn releases(&self) -> Result<Vec<Release>, ReleaseError>;
+    /// Opens an asset location returned by `releases`, continuing at `offset` if the source supports it.
+    fn open_at(&self, location: &str, offset: u64) -> Result<Download, ReleaseError>;
+
+    fn open(&self, location: &str) -> Result<Box<dyn Read>, ReleaseError> {
+        Ok(self.open_at(location, 0)?.reader)
+    }
+
+    /// Called when a file opened from `location` failed verification; true if another try can get it from elsewhere.
+    fn reject(&self, _location: &str) -> bool {
+        false
+    }
+}
+
+pub struct Download {
+    pub reader: Box<dyn Read>,
+    /// Where the reader starts, 0 if the source ignored the requested offset
+    pub offset: u64,
+    /// Size of the whole file, if known
+    pub total: Option<u64>,
+}
+
+/// The source configured in the machine policy, GitHub otherwise.
+pub fn from_policy(policy: &MachinePolicy) -> Result<Box<dyn UpdateSource>, Box<dyn Error>> {
+    let url = policy.update_url.clone();
+    let client = http_client::client(policy)?;
+    let download_client = http_client::download_client(policy)?;
+    match policy.update_source.as_deref().unwrap_or("GitHub").to_lowercase().as_str() {
+        "github" => Ok(Box::new(GitHubSource {
+            api_url: url.unwrap_or_else(|| DEFAULT_GITHUB_API.to_string()),
+            repository: policy.update_repository.clone().unwrap_or_else(|| DEFAULT_REPOSITORY.to_string()),
+            token: match &policy.update_token {
+                Some(protected) => Some(dpapi::unprotect(protected).map_err(|e| format!("UpdateToken: {}", e))?),
+                None => None,
+            },
+            client,
+            download_client,
+            cache_path: ResponseCache::get_cache_file_path(),
+        })),
+        "manifest" => Ok(Box::new(ManifestSource {
+            url: Url::parse(&url.ok_or("UpdateUrl is required for the manifest source")?)?,
+            client,
+            download_client,
+            cache_path: ResponseCache::get_cache_file_path(),
+        })),
+        "directory" => Ok(Box::new(DirectorySource::new(PathBuf::from(url.ok_or("UpdateUrl is required for the directory source")?)))),
+        other => Err(format!("unknown update source {}", other).into()),
+    }
+}
+
+/// The releases API of github.com or a GitHub Enterprise server ("https://github.example.com/api/v3").
+pub struct GitHubSource {
+    api_url: String,
+    repository: String,
+    /// Raises the limit from 60 to 5000 requests per hour and gives access to private repositories
+    token: Option<String>,
+    client: Client,
+    download_client: Client,
+    cache_path: PathBuf,
+}
+
+impl UpdateSource for GitHubSource {
+    fn releases(&self) -> Result<Vec<Release>, ReleaseError> {
+        let url = format!("{}/repos/{}/releases?per_page=50", self.api_url.trim_end_matches('/'), self.repository);
+        let body = get_cached(&self.client, &self.cache_path, &url, self.token.as_deref())?;
+        let mut releases: Vec<Release> = serde_json::from_str(&body).map_err(|e| ReleaseError::Invalid(format!("{}: {}", url, e)))?;
+        if self.token.is_some() {
+            // The browser URL does not take the token, so private assets are downloaded through the API.
+            for asset in releases.iter_mut().flat_map(|release| release.assets.iter_mut()) {
+                if let Some(api_url) = asset.url.take() {
+                    asset.browser_download_url = api_url;
+                }
+            }
+        }
+        Ok(releases)
+    }
+
+    fn open_at(&self, location: &str, offset: u64) -> Result<Download, ReleaseError> {
+        let request = self.download_client.get(location);
+        match &self.token {
+            // The API answers with a redirect to the file; the token is not sent along to the other host.
+            Some(token) => request_at(request.header(ACCEPT, "application/octet-stream").bearer_auth(token), offset),
+            None => request_at(request, offset),
+        }
+    }
+}
+
+/// A JSON file with a list of releases in the GitHub API format; relative asset URLs are resolved against the manifest URL.
+pub struct ManifestSource {
+    url: Url,
+    client: Client,
+    download_client: Client,
+    cache_path: PathBuf,
+}
+
+impl UpdateSource for ManifestSource {
+    fn releases(&self) -> Result<Vec<Release>, ReleaseError> {
+        let body = get_cached(&self.client, &self.cache_path, self.url.as_str(), None)?;
+        let mut releases: Vec<Release> = serde_json::from_str(&body)
+            .map_err(|e| ReleaseError::Invalid(format!("{}: {}", self.url, e)))?;
+        for asset in releases.iter_mut().flat_map(|release| release.assets.iter_mut()) {
+            asset.browser_download_url = self.url.join(&asset.browser_download_url)
+                .map_err(|e| ReleaseError::Invalid(format!("{}: {}", asset.browser_download_url, e)))?
+                .to_string();
+        }
+        Ok(releases)
+    }
+
+    fn open_at(&self, location: &str, offset: u64) -> Result<Download, ReleaseError> {
+        http_get_at(&self.download_client, location, offset)
+    }
+}
+
+/// A local or UNC folder with one subfolder per release tag, e.g. "\\server\kiosk\v1.4.0\winkioskshell.exe".
+pub struct DirectorySource {
+    path: PathBuf,
+}
+
+impl DirectorySource {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+}
+
+impl UpdateSource for DirectorySource {
+    fn releases(&self) -> Result<Vec<Release>, ReleaseError> {
+        let mut releases = Vec::new();
+        for entry in fs::read_dir(&self.path)?.flatten() {
+            if !entry.path().is_dir() {
+                continue;
+            }
+            let assets = fs::read_dir(entry.path())?
+                .flatten()
+                .filter(|file| file.path().is_file())
+                .map(|file| Asset {
+                    name: file.file_name().to_string_lossy().to_string(),
+                    browser_download_url: file.path().to_string_lossy().to_string(),
+                    url: None,
+                    digest: None,
+                    content_type: None,
+                })
+                .collect();
+            releases.push(Release::new(entry.file_name().to_string_lossy().to_string(), assets));
+        }
+        Ok(releases)
+    }
+
+    fn open_at(&self, location: &str, offset: u64) -> Result<Download, ReleaseError> {
+        let mut file = File::open(location)?;
+        let total = file.metadata()?.len();
+        let offset = offset.min(total);
+        file.seek(SeekFrom::Start(offset))?;
+        Ok(Download { reader: Box::new(file), offset, total: Some(total) })
+    }
+}
+
+/// The last release list with its ETag, so an unchanged list does not count against the rate limit,
+/// and the time the server asked us to wait for.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct ResponseCache {
+    url: String,
+    etag: Option<String>,
+    body: String,
+    retry_at: Option<u64>,
+}
+
+impl ResponseCache {
+    fn load(path: &Path, url: &str) -> Self {
+        let cache: Self = confy::load_path(path).unwrap_or_default();
+        if cache.url == url { cache } else { Self { url: url.to_string(), ..Self::default() } }
+    }
+
+    fn save(&self, path: &Path) {
+        let _ = confy::store_path(path, self);
+    }
+
+    /// The cached list while rate limited, if there is one.
+    fn rate_limited(self, retry_at: u64) -> Result<String, ReleaseError> {
+        if self.body.is_empty() {
+            Err(ReleaseError::RateLimited { retry_at })
+        } else {
+            Ok(self.body)
+        }
+    }
+
+    fn get_cache_file_path() -> PathBuf {
+        let mut file_path = ConfigManager::get_configuration_file_path(&"");
+        file_path.set_file_name("release_cache.ron");
+        file_path
+    }
+}
+
+/// GET with If-None-Match; a 304 and a rate limit answer both return the cached body.
+fn get_cached(client: &Client, cache_path: &Path, url: &str, token: Option<&str>) -> Result<String, ReleaseError> {
+    let mut cache = ResponseCache::load(cache_path, url);
+    if let Some(retry_at) = cache.retry_at.filter(|retry_at| *retry_at > unix_now()) {
+        return cache.rate_limited(retry_at);
+    }
+
+    let mut request = client.get(url);
+    if let Some(etag) = cache.etag.as_ref().filter(|_| !cache.body.is_empty()) {
+        request = request.header(IF_NONE_MATCH, etag);
+    }
+    if let Some(token) = token {
+        request = request.bearer_auth(token);
+    }
+    let response = request.send()?;
+    if response.status() == StatusCode::NOT_MODIFIED {
+        return Ok(cache.body);
+    }
+
+    let response = match check_status(response) {
+        Err(ReleaseError::RateLimited { retry_at }) => {
+            cache.retry_at = Some(retry_at);
+            cache.save(cache_path);
+            return cache.rate_limited(retry_at);
+        },
+        result => result?,
+    };
+    cache.etag = response.headers().get(ETAG).and_then(|etag| etag.to_str().ok()).map(str::to_string);
+    cache.body = response.text()?;
+    cache.retry_at = None;
+    cache.save(cache_path);
+    Ok(cache.body)
+}
+
+/// Requests the rest of the file with a Range header; a server without range support sends it from the start.
+pub fn http_get_at(client: &Client, url: &str, offset: u64) -> Result<Download, ReleaseError> {
+    request_at(client.get(url), offset)
+}
+
+/// `http_get_at` for a request with additional headers.
+pub fn request_at(request: RequestBuilder, offset: u64) -> Result<Download, ReleaseError> {
+    if offset == 0 {
+        let response = check_status(request.send()?)?;
+        let total = response.content_length();
+        return Ok(Download { reader: Box::new(response), offset: 0, total });
+    }
+
+    let response = request
+        .header(RANGE, format!("bytes={}-", offset))
+        .send()?;
+    match response.status() {
+        // The partial file is already complete.
+        StatusCode::RANGE_NOT_SATISFIABLE => Ok(Download { reader: Box::new(io::empty()), offset, total: Some(offset) }),
+        StatusCode::PARTIAL_CONTENT => {
+            let total = response.content_length().map(|remaining| offset + remaining);
+            Ok(Download { reader: Box::new(response), offset, total })
+        },
+        _ => {
+            let response = check_status(response)?;
+            let total = response.content_length();
+            Ok(Download { reader: Box::new(response), offset: 0, total })
+        },
+    }
+}
+
+pub fn http_get(client: &Client, url: &str) -> Result<Response, ReleaseError> {
+    check_status(client.get(url).send()?)
+}
+
+/// Turns 429, or 403 with Retry-After or an exhausted X-RateLimit-Remaining, into `RateLimited`
+/// and other error statuses into `Status` with the message of the body, e.g. GitHub's {"message": "Not Found"}.
+fn check_status(response: Response) -> Result<Response, ReleaseError> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+
+    let header = |name: &str| response.headers().get(name)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse::<u64>().ok());
+    let retry_after = header(RETRY_AFTER.as_str()).map(|seconds| unix_now() + seconds);
+    let rate_limit_reset = if header("x-ratelimit-remaining") == Some(0) { header("x-ratelimit-reset") } else { None };
+    if status == StatusCode::TOO_MANY_REQUESTS || (status == StatusCode::FORBIDDEN && (retry_after.is_some() || rate_limit_reset.is_some())) {
+        let retry_at = retry_after.or(rate_limit_reset).unwrap_or_else(|| unix_now() + DEFAULT_RETRY_SECS);
+        return Err(ReleaseError::RateLimited { retry_at });
+    }
+
+    let body = response.text().unwrap_or_default();
+    let message = serde_json::from_str::<serde_json::Value>(&body).ok()
+        .and_then(|value| value.get("message").and_then(|message| message.as_str()).map(str::to_string))
+        .unwrap_or_else(|| body.trim().chars().take(200).collect());
+    Err(ReleaseError::Status { status: status.as_u16(), message })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{io::Cursor, sync::{Arc, Mutex}, thread::{self, JoinHandle}};
+    use tiny_http::{Header, Request, Server};
+    use crate::test_support::TempPath;
+
+    type StubResponse = tiny_http::Response<Cursor<Vec<u8>>>;
+
+    /// A local HTTP server answering with `handler` and recording the headers of every request.
+    struct Stub {
+        url: String,
+        requests: Arc<Mutex<Vec<Vec<(String, String)>>>>,
+        server: Arc<Server>,
+        worker: Option<JoinHandle<()>>,
+    }
+
+    impl Stub {
+        fn start(handler: impl Fn(&Request, usize) -> StubResponse + Send + 'static) -> Self {
+            let server = Arc::new(Server::http("127.0.0.1:0").unwrap());
+            let url = format!("http://{}", server.server_addr().to_ip().unwrap());
+            let requests = Arc::new(Mutex::new(Vec::new()));
+            let worker = {
+                let server = server.clone();
+                let requests = requests.clone();
+                thread::spawn(move || {
+                    for request in server.incoming_requests() {
+                        let headers = request.headers().iter().map(|header| (header.field.to_string(), header.value.to_string())).collect();
+                        let index = {
+                            let mut requests = requests.lock().unwrap();
+                            requests.push(headers);
+                            requests.len() - 1
+                        };
+                        let response = handler(&request, index);
+                        let _ = request.respond(response);
+                    }
+                })
+            };
+            Self { url, requests, server, worker: Some(worker) }
+        }
+
+        fn request_count(&self) -> usize {
+            self.requests.lock().unwrap().len()
+        }
+
+        fn header(&self, index: usize, name: &str) -> Option<String> {
+            self.requests.lock().unwrap()[index].iter()
+                .find(|(field, _)| field.eq_ignore_ascii_case(name))
+                .map(|(_, value)| value.clone())
+        }
+    }
+
+    impl Drop for Stub {
+        fn drop(&mut self) {
+            self.server.unblock();
+            if let Some(worker) = self.worker.take() {
+                let _ = worker.join();
+            }
+        }
+    }
+
+    fn response(status: u16, body: &str, headers: &[(&str, &str)]) -> StubResponse {
+        headers.iter().fold(
+            tiny_http::Response::from_data(body.as_bytes().to_vec()).with_status_code(status),
+            |response, (field, value)| response.with_header(Header::from_bytes(*field, *value).unwrap()),
+        )
+    }
+
+    fn request_header(request: &Request, name: &'static str) -> Option<String> {
+        request.headers().iter().find(|header| header.field.equiv(name)).map(|header| header.value.to_string())
+    }
+
+    /// Without the proxy of the machine, which would not reach 127.0.0.1.
+    fn client() -> Client {
+        Client::builder().no_proxy().build().unwrap()
+    }
+
+    fn github_source(stub: &Stub, cache_path: &Path) -> GitHubSource {
+        GitHubSource {
+            api_url: stub.url.clone(),
+            repository: "owner/kiosk".to_string(),
+            token: Some("secret".to_string()),
+            client: client(),
+            download_client: client(),
+            cache_path: cache_path.to_path_buf(),
+        }
+    }
+
+    const RELEASES: &str = r#"[{"tag_name": "v1.1.0", "assets": [{"name": "winkioskshell.exe", "browser_download_url": "https://example.com/winkioskshell.exe"}]}]"#;
+
+    #[test]
+    fn github_releases_are_cached_with_etag() {
+        let stub = Stub::start(|request, _| {
+            assert_eq!(request.url(), "/repos/owner/kiosk/releases?per_page=50");
+            match request_header(request, "If-None-Match") {
+                Some(etag) if etag == "\"v1\"" => response(304, "", &[]),
+                _ => response(200, RELEASES, &[("ETag", "\"v1\"")]),
+            }
+        });
+        let cache_path = TempPath::new("etag.ron");
+        let source = github_source(&stub, &cache_path);
+
+        let releases = source.releases().unwrap();
+        assert_eq!(releases[0].version(), "v1.1.0");
+        assert_eq!(stub.header(0, "Authorization").as_deref(), Some("Bearer secret"));
+        assert_eq!(stub.header(0, "If-None-Match"), None);
+
+        // The second answer is a 304 without a body, the list comes from the cache.
+        let releases = source.releases().unwrap();
+        assert_eq!(releases[0].assets[0].name, "winkioskshell.exe");
+        assert_eq!(stub.header(1, "If-None-Match").as_deref(), Some("\"v1\""));
+    }
+
+    #[test]
+    fn github_assets_are_downloaded_through_the_api_with_the_token() {
+        let stub = Stub::start(|request, _| match request.url() {
+            "/repos/owner/kiosk/releases?per_page=50" => {
+                let api_url = format!("http://{}/repos/owner/kiosk/releases/assets/7", request_header(request, "Host").unwrap_or_default());
+                response(200, &format!(r#"[{{"tag_name": "v1.1.0", "assets": [{{"name": "winkioskshell.exe", "url": "{}", "browser_download_url": "https://example.com/winkioskshell.exe"}}]}}]"#, api_url), &[])
+            },
+            "/repos/owner/kiosk/releases/assets/7" => response(206, "456789", &[]),
+            _ => response(404, "", &[]),
+        });
+        let cache_path = TempPath::new("private_asset.ron");
+        let source = github_source(&stub, &cache_path);
+
+        let releases = source.releases().unwrap();
+        let location = &releases[0].assets[0].browser_download_url;
+        assert_eq!(*location, format!("{}/repos/owner/kiosk/releases/assets/7", stub.url));
+
+        let download = source.open_at(location, 4).unwrap();
+        assert_eq!(download.offset, 4);
+        assert_eq!(read_all(download), "456789");
+        assert_eq!(stub.header(1, "Accept").as_deref(), Some("application/octet-stream"));
+        assert_eq!(stub.header(1, "Authorization").as_deref(), Some("Bearer secret"));
+        assert_eq!(stub.header(1, "Range").as_deref(), Some("bytes=4-"));
+    }
+
+    #[test]
+    fn rate_limit_returns_the_cached_list_and_backs_off() {
+        let stub = Stub::start(|_, index| match index {
+            0 => response(200, RELEASES, &[("ETag", "\"v1\"")]),
+            _ => response(429, "", &[("Retry-After", "600")]),
+        });
+        let cache_path = TempPath::new("rate_limit.ron");
+        let source = github_source(&stub, &cache_path);
+
+        assert_eq!(source.releases().unwrap().len(), 1);
+        assert_eq!(source.releases().unwrap().len(), 1);
+        assert_eq!(stub.request_count(), 2);
+
+        // No request at all until Retry-After has passed.
+        assert_eq!(source.releases().unwrap().len(), 1);
+        assert_eq!(stub.request_count(), 2);
+    }
+
+    #[test]
+    fn rate_limit_without_cached_list_is_an_error() {
+        let stub = Stub::start(|_, _| response(403, r#"{"message": "API rate limit exceeded"}"#, &[("x-ratelimit-remaining", "0"), ("x-ratelimit-reset", "4102444800")]));
+        let cache_path = TempPath::new("rate_limit_error.ron");
+        let source = github_source(&stub, &cache_path);
+
+        assert!(matches!(source.releases(), Err(ReleaseError::RateLimited { retry_at: 4102444800 })));
+        assert!(matches!(source.releases(), Err(ReleaseError::RateLimited { retry_at: 4102444800 })));
+        assert_eq!(stub.request_count(), 1);
+    }
+
+    #[test]
+    fn error_status_carries_the_message() {
+        let stub = Stub::start(|_, _| response(404, r#"{"message": "Not Found"}"#, &[]));
+        let cache_path = TempPath::new("not_found.ron");
+        let source = github_source(&stub, &cache_path);
+
+        match source.releases() {
+            Err(ReleaseError::Status { status, message }) => assert_eq!((status, message.as_str()), (404, "Not Found")),
+            other => panic!("unexpected {:?}", other.map(|releases| releases.len())),
+        }
+    }
+
+    #[test]
+    fn manifest_resolves_relative_asset_urls() {
+        let stub = Stub::start(|request, _| {
+            assert_eq!(request.url(), "/kiosk/releases.json");
+            response(200, r#"[{"tag_name": "v1.1.0", "assets": [{"name": "winkioskshell.exe", "browser_download_url": "v1.1.0/winkioskshell.exe"}, {"name": "SHA256SUMS", "browser_download_url": "https://cdn.example.com/SHA256SUMS"}]}]"#, &[])
+        });
+        let cache_path = TempPath::new("manifest.ron");
+        let source = ManifestSource {
+            url: Url::parse(&format!("{}/kiosk/releases.json", stub.url)).unwrap(),
+            client: client(),
+            download_client: client(),
+            cache_path: cache_path.to_path_buf(),
+        };
+
+        let releases = source.releases().unwrap();
+        assert_eq!(releases[0].assets[0].browser_download_url, format!("{}/kiosk/v1.1.0/winkioskshell.exe", stub.url));
+        assert_eq!(releases[0].assets[1].browser_download_url, "https://cdn.example.com/SHA256SUMS");
+        assert_eq!(stub.header(0, "Authorization"), None);
+    }
+
+    fn read_all(download: Download) -> String {
+        let mut content = String::new();
+        let mut reader = download.reader;
+        reader.read_to_string(&mut content).unwrap();
+        content
+    }
+
+    #[test]
+    fn download_resumes_with_range() {
+        let stub = Stub::start(|request, _| {
+            let content = "0123456789";
+            match request_header(request, "Range").and_then(|range| range.strip_prefix("bytes=")?.strip_suffix('-')?.parse::<usize>().ok()) {
+                Some(offset) if offset >= content.len() => response(416, "", &[]),
+                Some(offset) => response(206, &content[offset..], &[]),
+                None => response(200, content, &[]),
+            }
+        });
+        let client = client();
+        let url = format!("{}/winkioskshell.exe", stub.url);
+
+        let download = http_get_at(&client, &url, 0).unwrap();
+        assert_eq!((download.offset, download.total), (0, Some(10)));
+        assert_eq!(read_all(download), "0123456789");
+
+        let download = http_get_at(&client, &url, 4).unwrap();
+        assert_eq!((download.offset, download.total), (4, Some(10)));
+        assert_eq!(stub.header(1, "Range").as_deref(), Some("bytes=4-"));
+        assert_eq!(read_all(download), "456789");
+
+        // Already complete
+        let download = http_get_at(&client, &url, 10).unwrap();
+        assert_eq!((download.offset, download.total), (10, Some(10)));
+        assert_eq!(read_all(download), "");
+    }
+
+    #[test]
+    fn download_starts_over_without_range_support() {
+        let stub = Stub::start(|_, _| response(200, "0123456789", &[]));
+        let download = http_get_at(&client(), &format!("{}/winkioskshell.exe", stub.url), 4).unwrap();
+        assert_eq!((download.offset, download.total), (0, Some(10)));
+        assert_eq!(read_all(download), "0123456789");
+    }
+
+    #[test]
+    fn directory_lists_one_release_per_folder() {
+        let path = TempPath::new("directory");
+        for (folder, content) in [("v1.0.0", "old"), ("v1.1.0", "0123456789")] {
+            fs::create_dir_all(path.join(folder)).unwrap();
+            fs::write(path.join(folder).join("winkioskshell.exe"), content).unwrap();
+        }
+        fs::write(path.join("readme.txt"), "not a release").unwrap();
+        let source = DirectorySource::new(path.to_path_buf());
+
+        let mut releases = source.releases().unwrap();
+        releases.sort_by(|a, b| a.version().cmp(b.version()));
+        assert_eq!(releases.iter().map(Release::version).collect::<Vec<_>>(), ["v1.0.0", "v1.1.0"]);
+
+        let location = &releases[1].assets[0].browser_download_url;
+        let download = source.open_at(location, 4).unwrap();
+        assert_eq!((download.offset, download.total), (4, Some(10)));
+        assert_eq!(read_all(download), "456789");
+    }
+}
@@ -0,0 +1,560 @@
+use std::{env, error::Error, fs, path::{Path, PathBuf}, process::{Child, Command}, sync::{Arc, Condvar, Mutex, Weak}, thread::{self, JoinHandle}, time::{Duration, Instant}};
+use lazy_static::lazy_static;
+use rand::Rng;
+use serde::{Serialize, Deserialize};
+use winapi::um::handleapi::CloseHandle;
+use winapi::shared::winerror::WAIT_TIMEOUT;
+use winapi::um::processthreadsapi::{GetCurrentProcessId, OpenProcess, TerminateProcess};
+use winapi::um::synchapi::WaitForSingleObject;
+use winapi::um::sysinfoapi::GetTickCount;
+use winapi::um::winnt::{PROCESS_TERMINATE, SYNCHRONIZE};
+use winapi::um::winuser::{GetLastInputInfo, LASTINPUTINFO};
+
+use crate::{app_deployment, audit_log::AuditLog, login_guard::unix_now, machinepolicy::MachinePolicy, mainconfig::{ConfigManager, MainConfig}, release::{self, Release}, update_cache, update_source::{self, UpdateSource}};
+
+
+/// Folder on a removable drive with an update package for offline kiosks
+pub const USB_UPDATE_FOLDER: &str = "winkioskshell-update";
+/// Argument of a restarted shell, followed by the process ID of the instance it replaces
+pub const WAIT_FOR_PID_ARGUMENT: &str = "--wait-for-pid";
+/// A previous instance that has not exited by then is hanging and gets terminated.
+const WAIT_FOR_PID_TIMEOUT_MS: u32 = 30_000;
+/// How long exiting waits for the update checker to finish.
+const CHECKER_STOP_TIMEOUT: Duration = Duration::from_secs(2);
+
+
+#[derive(Debug, Clone)]
+pub struct StagedUpdate {
+    pub version: String,
+    pub path: PathBuf,
+    /// Staged from a USB drive, installed on schedule only with UsbUpdateAutoInstall and otherwise by an administrator
+    pub from_drive: bool,
+}
+
+#[derive(Debug, Clone)]
+pub enum UpdateStatus {
+    UpToDate,
+    Downloading { version: String, downloaded: u64, total: Option<u64> },
+    Staged(StagedUpdate),
+    Skipped(String),
+    RolledBack(String),
+    Failed(String),
+}
+
+impl UpdateStatus {
+    pub fn message(&self) -> String {
+        match self {
+            UpdateStatus::UpToDate => format!("Version {} is up to date", env!("APP_VERSION")),
+            UpdateStatus::Downloading { version, downloaded, total } => match total {
+                Some(total) if *total > 0 => format!("Downloading update {}: {}%", version, downloaded * 100 / total),
+                _ => format!("Downloading update {}: {} KB", version, downloaded / 1024),
+            },
+            UpdateStatus::Staged(staged) => format!("Update {} ready, installed when the kiosk is idle", staged.version),
+            UpdateStatus::Skipped(version) => format!("Update {} is skipped, it failed to start before", version),
+            UpdateStatus::RolledBack(version) => format!("Update {} failed to start and was rolled back", version),
+            UpdateStatus::Failed(e) => format!("Update failed: {}", e),
+        }
+    }
+
+    /// Between 0 and 1 while downloading.
+    pub fn progress(&self) -> Option<f32> {
+        match self {
+            UpdateStatus::Downloading { downloaded, total: Some(total), .. } if *total > 0 => Some(*downloaded as f32 / *total as f32),
+            _ => None,
+        }
+    }
+}
+
+/// Tracks the starts of a freshly installed version, so a broken update can be rolled back.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct UpdateState {
+    pending_version: Option<String>,
+    previous_version: Option<String>,
+    boot_attempts: u32,
+    failed_versions: Vec<String>,
+    rolled_back_version: Option<String>,
+    /// The version waiting for installation and since when, to force it after the maximum deferral
+    deferred_version: Option<String>,
+    deferred_since: Option<u64>,
+    last_check: Option<LastCheck>,
+}
+
+/// Result of the latest update check, shown in the settings window.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LastCheck {
+    pub checked_at: u64,
+    pub result: String,
+    pub latest_version: Option<String>,
+    pub published_at: Option<String>,
+    pub release_notes: Option<String>,
+}
+
+impl UpdateState {
+    fn load() -> Self {
+        confy::load_path(Self::get_state_file_path()).unwrap_or_default()
+    }
+
+    fn save(&self) {
+        let _ = confy::store_path(Self::get_state_file_path(), self);
+    }
+
+    fn get_state_file_path() -> PathBuf {
+        let mut file_path = ConfigManager::get_configuration_file_path(&"");
+        file_path.set_file_name("update_state.ron");
+        file_path
+    }
+}
+
+lazy_static! {
+    // Only updates verified by this process are installed, never a file that happens to lie in the staging folder.
+    static ref STAGED_UPDATE: Mutex<Option<StagedUpdate>> = Mutex::new(None);
+    static ref STAGING: Mutex<()> = Mutex::new(());
+    static ref RUNNING_CHECKER: Mutex<Weak<UpdateChecker>> = Mutex::new(Weak::new());
+}
+
+pub fn staged_update() -> Option<StagedUpdate> {
+    STAGED_UPDATE.lock().unwrap().clone()
+}
+
+/// The staged update, if it may be installed without an administrator once the kiosk is idle in a maintenance window.
+pub fn scheduled_update() -> Option<StagedUpdate> {
+    staged_update().filter(|staged| !staged.from_drive || MachinePolicy::load().usb_update_auto_install)
+}
+
+/// Downloads and verifies a newer release and stages it for installation; `on_progress` receives the download progress.
+pub fn check_for_update(on_progress: &dyn Fn(UpdateStatus), is_cancelled: &dyn Fn() -> bool) -> UpdateStatus {
+    let status = download_update(on_progress, is_cancelled).unwrap_or_else(|e| UpdateStatus::Failed(e.to_string()));
+    // The shell is exiting, the check is repeated at the next start.
+    if is_cancelled() {
+        return status;
+    }
+
+    if let UpdateStatus::Failed(e) = &status {
+        AuditLog::write("update_check_failed", e);
+    }
+    let mut state = UpdateState::load();
+    let last_check = state.last_check.get_or_insert_with(LastCheck::default);
+    last_check.checked_at = unix_now();
+    last_check.result = status.message();
+    state.save();
+    status
+}
+
+pub fn last_check() -> Option<LastCheck> {
+    UpdateState::load().last_check
+}
+
+fn download_update(on_progress: &dyn Fn(UpdateStatus), is_cancelled: &dyn Fn() -> bool) -> Result<UpdateStatus, Box<dyn Error>> {
+    let config = ConfigManager::load_config(&"");
+    let channel = config.update_channel;
+    let policy = MachinePolicy::load();
+    release::check_cancelled(is_cancelled)?;
+    let source = update_cache::with_peers(update_source::from_policy(&policy)?, &config, &policy)?;
+    release::check_cancelled(is_cancelled)?;
+    let release = release::get_latest_release(source.as_ref(), channel, &policy)?;
+    let mut state = UpdateState::load();
+    state.last_check = Some(LastCheck {
+        latest_version: Some(release.version().to_string()),
+        published_at: release.published_at.clone(),
+        release_notes: release.body.clone(),
+        ..state.last_check.unwrap_or_default()
+    });
+    state.save();
+    stage_release(source.as_ref(), &release, &config, &policy, false, on_progress, is_cancelled)
+}
+
+/// Stages an update package from a removable drive, a folder "winkioskshell-update" in the layout of the directory source.
+/// Returns None if the drive has no package.
+pub fn stage_from_drive(drive: &Path) -> Result<Option<UpdateStatus>, Box<dyn Error>> {
+    let path = drive.join(USB_UPDATE_FOLDER);
+    if !path.is_dir() {
+        return Ok(None);
+    }
+    let config = ConfigManager::load_config(&"");
+    let policy = MachinePolicy::load();
+    let source = update_source::DirectorySource::new(path);
+    let release = release::get_latest_release(&source, config.update_channel, &policy)?;
+    Ok(Some(stage_release(&source, &release, &config, &policy, true, &|_| {}, &|| false)?))
+}
+
+/// Downloads, verifies and stages the release unless it is installed already, failed before or is staged.
+fn stage_release(source: &dyn UpdateSource, release: &Release, config: &MainConfig, policy: &MachinePolicy, from_drive: bool, on_progress: &dyn Fn(UpdateStatus), is_cancelled: &dyn Fn() -> bool) -> Result<UpdateStatus, Box<dyn Error>> {
+    // The background check and a drive inserted meanwhile must not write the same files.
+    let _staging = STAGING.lock().unwrap();
+    if !release::is_update_available(release, policy)? {
+        return Ok(UpdateStatus::UpToDate);
+    }
+
+    let version = release.version().to_string();
+    if UpdateState::load().failed_versions.contains(&version) {
+        return Ok(UpdateStatus::Skipped(version));
+    }
+    if let Some(staged) = staged_update().filter(|staged| staged.version == version) {
+        return Ok(UpdateStatus::Staged(staged));
+    }
+
+    let asset = release.select_asset(policy.update_asset_pattern.as_deref())
+        .ok_or_else(|| format!("release {} has no asset for {}", version, env::consts::ARCH))?;
+    let public_key = release::update_public_key().ok_or("no public key to verify the release signature")?;
+
+    let staging_directory = get_staging_directory();
+    fs::create_dir_all(&staging_directory)?;
+    // Kept next to the staged files, so a partial download survives a reboot.
+    let download_path = staging_directory.join(format!("{}-{}.download", env!("CARGO_PKG_NAME"), version));
+    let staged_path = staging_directory.join(format!("{}-{}.exe", env!("CARGO_PKG_NAME"), version));
+
+    let options = release::DownloadOptions {
+        // The bandwidth limit is for metered connections, a local drive is read at full speed.
+        bytes_per_sec: config.download_limit_kb_per_sec.map(|limit| limit * 1024).filter(|_| !from_drive),
+        on_progress: &|downloaded, total| on_progress(UpdateStatus::Downloading { version: version.clone(), downloaded, total }),
+        is_cancelled,
+    };
+    release::download_patched_or_full_release(source, release, asset, &public_key, env::current_exe().ok().as_deref(), &download_path, &options)?;
+    if config.update_cache_port.is_some() {
+        if let Err(e) = update_cache::store(release, asset, &download_path) {
+            AuditLog::write("update_cache_failed", &format!("{}: {}", version, e));
+        }
+    }
+    if asset.is_zip() {
+        let result = release::extract_executable(&download_path, &staged_path);
+        let _ = fs::remove_file(&download_path);
+        result?;
+    } else {
+        fs::rename(&download_path, &staged_path)?;
+    }
+
+    let staged = StagedUpdate { version, path: staged_path, from_drive };
+    let mut state = UpdateState::load();
+    if state.deferred_version.as_ref() != Some(&staged.version) {
+        state.deferred_version = Some(staged.version.clone());
+        state.deferred_since = Some(unix_now());
+        state.save();
+    }
+    AuditLog::write("update_staged", &staged.version);
+    *STAGED_UPDATE.lock().unwrap() = Some(staged.clone());
+    Ok(UpdateStatus::Staged(staged))
+}
+
+/// The staged update waited longer than `update_max_deferral_hours` for a maintenance window or an idle kiosk,
+/// or the installed version is below the minimum version of the machine policy.
+pub fn is_install_overdue(config: &MainConfig) -> bool {
+    let Some(staged) = scheduled_update() else {
+        return false;
+    };
+    if release::is_below_minimum_version(&MachinePolicy::load()) {
+        return true;
+    }
+    let Some(max_deferral_hours) = config.update_max_deferral_hours else {
+        return false;
+    };
+    let state = UpdateState::load();
+    state.deferred_version.as_ref() == Some(&staged.version)
+        && state.deferred_since.is_some_and(|since| unix_now().saturating_sub(since) >= max_deferral_hours * 3600)
+}
+
+/// Swaps the running executable with the staged one; the new version runs after the next start.
+pub fn install_staged_update() -> Result<Option<String>, Box<dyn Error>> {
+    let Some(staged) = STAGED_UPDATE.lock().unwrap().take() else {
+        return Ok(None);
+    };
+
+    // Keep the running version for a rollback.
+    let result = env::current_exe()
+        .and_then(|current_exe| fs::copy(current_exe, get_previous_executable_path()))
+        .and_then(|_| self_replace::self_replace(&staged.path));
+    if let Err(e) = result {
+        AuditLog::write("update_install_failed", &format!("{}: {}", staged.version, e));
+        return Err(e.into());
+    }
+    let _ = fs::remove_file(&staged.path);
+
+    let mut state = UpdateState::load();
+    state.deferred_version = None;
+    state.deferred_since = None;
+    state.pending_version = Some(staged.version.clone());
+    state.previous_version = Some(env!("APP_VERSION").to_string());
+    state.boot_attempts = 0;
+    state.save();
+
+    AuditLog::write("update_installed", &format!("{} -> {}", env!("APP_VERSION"), staged.version));
+    Ok(Some(staged.version))
+}
+
+/// Installs the staged update, stops the client application and starts the new shell version; returns the installed
+/// version, after which the caller has to leave its event loop so the new instance can take over.
+pub fn install_update_and_restart(client: Option<&Mutex<Child>>) -> Result<Option<String>, Box<dyn Error>> {
+    let Some(version) = install_staged_update()? else {
+        return Ok(None);
+    };
+
+    if let Some(client) = client {
+        let mut client = client.lock().unwrap();
+        let _ = client.kill();
+        let _ = client.wait();
+    }
+
+    restart_shell()?;
+    Ok(Some(version))
+}
+
+/// Called at every start. Restores the previous executable when a new version did not become healthy
+/// within the allowed number of starts; returns true if the caller should exit for the restarted old version.
+pub fn record_start() -> bool {
+    let mut state = UpdateState::load();
+    let Some(pending_version) = state.pending_version.clone() else {
+        return false;
+    };
+    if !release::is_same_version(&pending_version, env!("APP_VERSION")) {
+        // Someone replaced the executable by hand.
+        state.pending_version = None;
+        state.save();
+        return false;
+    }
+
+    state.boot_attempts += 1;
+    let max_boot_attempts = ConfigManager::load_config(&"").update_max_boot_attempts.max(1);
+    if state.boot_attempts <= max_boot_attempts {
+        state.save();
+        return false;
+    }
+
+    state.pending_version = None;
+    let result = self_replace::self_replace(get_previous_executable_path());
+    match result {
+        Ok(()) => {
+            AuditLog::write("update_rolled_back", &format!("{} -> {}", pending_version, state.previous_version.clone().unwrap_or_default()));
+            state.failed_versions.push(pending_version.clone());
+            state.rolled_back_version = Some(pending_version);
+            state.save();
+            restart_shell().is_ok()
+        },
+        Err(e) => {
+            AuditLog::write("update_rollback_failed", &format!("{}: {}", pending_version, e));
+            state.save();
+            false
+        },
+    }
+}
+
+/// The new version showed its UI and kept the client application running, no rollback needed anymore.
+pub fn mark_healthy() {
+    let mut state = UpdateState::load();
+    if let Some(version) = state.pending_version.take() {
+        AuditLog::write("update_healthy", &format!("{} after {} starts", version, state.boot_attempts));
+        state.boot_attempts = 0;
+        state.save();
+    }
+}
+
+pub fn is_update_pending() -> bool {
+    UpdateState::load().pending_version.is_some()
+}
+
+/// Reports a rollback once, to show it in the UI after the old version restarted.
+pub fn take_rolled_back_version() -> Option<UpdateStatus> {
+    let mut state = UpdateState::load();
+    let version = state.rolled_back_version.take()?;
+    state.save();
+    Some(UpdateStatus::RolledBack(version))
+}
+
+/// Checks for shell and client application updates at startup and then every `update_check_hours`, off the UI thread.
+/// There is one per process; the shell and the settings window opened from it both subscribe to the same checker.
+pub struct UpdateChecker {
+    signal: CheckerSignal,
+    subscribers: Arc<Mutex<Subscribers>>,
+    worker: Option<JoinHandle<()>>,
+}
+
+#[derive(Default)]
+struct CheckerFlags {
+    stopped: bool,
+    check_requested: bool,
+}
+
+/// Wakes the checker thread, for a "check now" button.
+#[derive(Clone, Default)]
+pub struct CheckerSignal(Arc<(Mutex<CheckerFlags>, Condvar)>);
+
+impl CheckerSignal {
+    pub fn check_now(&self) {
+        let (flags, condvar) = &*self.0;
+        flags.lock().unwrap().check_requested = true;
+        condvar.notify_all();
+    }
+
+    fn stop(&self) {
+        let (flags, condvar) = &*self.0;
+        flags.lock().unwrap().stopped = true;
+        condvar.notify_all();
+    }
+
+    fn is_stopped(&self) -> bool {
+        self.0.0.lock().unwrap().stopped
+    }
+
+    /// Returns false when the checker is stopped.
+    fn wait(&self, timeout: Duration) -> bool {
+        let (flags, condvar) = &*self.0;
+        let guard = flags.lock().unwrap();
+        let (mut guard, _) = condvar.wait_timeout_while(guard, timeout, |flags| !flags.stopped && !flags.check_requested).unwrap();
+        guard.check_requested = false;
+        !guard.stopped
+    }
+}
+
+#[derive(Default)]
+struct Subscribers {
+    next_id: u64,
+    callbacks: Vec<(u64, Box<dyn Fn(UpdateStatus) + Send>)>,
+    last_status: Option<UpdateStatus>,
+}
+
+impl Subscribers {
+    fn notify(&mut self, status: UpdateStatus) {
+        for (_, callback) in &self.callbacks {
+            callback(status.clone());
+        }
+        self.last_status = Some(status);
+    }
+}
+
+/// Receives the status of the update checker until dropped.
+pub struct Subscription {
+    subscribers: Arc<Mutex<Subscribers>>,
+    id: u64,
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        self.subscribers.lock().unwrap().callbacks.retain(|(id, _)| *id != self.id);
+    }
+}
+
+impl UpdateChecker {
+    /// The checker of this process, started by the first caller and stopped when the last one drops it.
+    pub fn shared() -> Arc<Self> {
+        let mut running_checker = RUNNING_CHECKER.lock().unwrap();
+        if let Some(checker) = running_checker.upgrade() {
+            return checker;
+        }
+        let checker = Arc::new(Self::spawn());
+        *running_checker = Arc::downgrade(&checker);
+        checker
+    }
+
+    fn spawn() -> Self {
+        let signal = CheckerSignal::default();
+        let subscribers = Arc::new(Mutex::new(Subscribers::default()));
+
+        let signal_clone = signal.clone();
+        let subscribers_clone = subscribers.clone();
+        let worker = thread::spawn(move || {
+            let notify = |status: UpdateStatus| subscribers_clone.lock().unwrap().notify(status);
+            loop {
+                let is_cancelled = || signal_clone.is_stopped();
+                notify(check_for_update(&notify, &is_cancelled));
+                if let Err(e) = app_deployment::check_for_app_update(&is_cancelled).map_err(|e| e.to_string()) {
+                    if !is_cancelled() {
+                        AuditLog::write("app_update_check_failed", &e);
+                    }
+                }
+
+                if !signal_clone.wait(check_interval()) {
+                    break;
+                }
+            }
+        });
+
+        Self { signal, subscribers, worker: Some(worker) }
+    }
+
+    /// Calls `on_checked` with the last status right away and then with every new one.
+    pub fn subscribe(&self, on_checked: impl Fn(UpdateStatus) + Send + 'static) -> Subscription {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        if let Some(status) = subscribers.last_status.clone() {
+            on_checked(status);
+        }
+        let id = subscribers.next_id;
+        subscribers.next_id += 1;
+        subscribers.callbacks.push((id, Box::new(on_checked)));
+        Subscription { subscribers: self.subscribers.clone(), id }
+    }
+
+    pub fn signal(&self) -> CheckerSignal {
+        self.signal.clone()
+    }
+}
+
+impl Drop for UpdateChecker {
+    /// Waits only briefly: a request that is already running is not interrupted, and a shell restarted by an update
+    /// must not be kept waiting for it. A worker that is still busy is detached and ends with the process.
+    fn drop(&mut self) {
+        self.signal.stop();
+        if let Some(worker) = self.worker.take() {
+            let deadline = Instant::now() + CHECKER_STOP_TIMEOUT;
+            while !worker.is_finished() && Instant::now() < deadline {
+                thread::sleep(Duration::from_millis(50));
+            }
+            if worker.is_finished() {
+                let _ = worker.join();
+            }
+        }
+    }
+}
+
+/// The configured interval plus up to a quarter of it, so a fleet does not hit the server at the same time.
+fn check_interval() -> Duration {
+    let interval = Duration::from_secs(ConfigManager::load_config(&"").update_check_hours.max(1) * 3600);
+    interval + rand::thread_rng().gen_range(Duration::ZERO..=interval / 4)
+}
+
+/// Starts a new instance, which waits until this one has exited; the caller has to exit right after.
+pub fn restart_shell() -> Result<(), Box<dyn Error>> {
+    let pid = unsafe { GetCurrentProcessId() };
+    Command::new(env::current_exe()?).arg(WAIT_FOR_PID_ARGUMENT).arg(pid.to_string()).spawn()?;
+    Ok(())
+}
+
+/// Blocks until the process has exited, so two shells never run at the same time.
+pub fn wait_for_exit(pid: u32) {
+    unsafe {
+        let process = OpenProcess(SYNCHRONIZE | PROCESS_TERMINATE, 0, pid);
+        if process.is_null() {
+            // Already gone
+            return;
+        }
+        if WaitForSingleObject(process, WAIT_FOR_PID_TIMEOUT_MS) == WAIT_TIMEOUT {
+            AuditLog::write("shell_restart_terminated", &format!("previous instance {} did not exit", pid));
+            TerminateProcess(process, 1);
+            WaitForSingleObject(process, WAIT_FOR_PID_TIMEOUT_MS);
+        }
+        CloseHandle(process);
+    }
+}
+
+/// Time since the last keyboard, mouse or touch input of any application.
+pub fn idle_time() -> Duration {
+    unsafe {
+        let mut last_input = LASTINPUTINFO {
+            cbSize: std::mem::size_of::<LASTINPUTINFO>() as u32,
+            dwTime: 0,
+        };
+        if GetLastInputInfo(&mut last_input) == 0 {
+            return Duration::ZERO;
+        }
+        Duration::from_millis(GetTickCount().wrapping_sub(last_input.dwTime) as u64)
+    }
+}
+
+fn get_previous_executable_path() -> PathBuf {
+    get_staging_directory().join(format!("{}-previous.exe", env!("CARGO_PKG_NAME")))
+}
+
+fn get_staging_directory() -> PathBuf {
+    let mut directory = ConfigManager::get_configuration_file_path(&"");
+    directory.set_file_name("update");
+    directory
+}
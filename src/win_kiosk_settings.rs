@@ -1,10 +1,11 @@
-use std::{env, error::Error, fs, os::windows::process::CommandExt, path::Path, process::{Command, Output, Stdio}, rc::Rc, str::FromStr};
-use slint::{self, ComponentHandle, ModelRc, SharedString, VecModel};
+use std::{cell::RefCell, env, error::Error, fs, os::windows::process::CommandExt, path::Path, process::{Child, Command, Output, Stdio}, rc::Rc, str::FromStr, sync::{Arc, Mutex}};
+use qrcode::{Color, QrCode};
+use slint::{self, ComponentHandle, Image, ModelRc, Rgb8Pixel, SharedPixelBuffer, SharedString, VecModel};
 use winreg::{enums::KEY_WRITE, RegKey};
 use mslnk::ShellLink;
 use winapi::um::winbase::CREATE_NO_WINDOW;
 
-use crate::{mainconfig::ConfigManager, win_kiosk_shell::check_for_update};
+use crate::{login_guard::unix_now, mainconfig::ConfigManager, release, totp, updater};
 use crate::win_elevation_functions;
 
 
@@ -13,6 +14,15 @@ slint::include_modules!();
 
 #[derive(Default)]
 pub struct WinKioskSettings {
+    on_shown: Option<Box<dyn FnOnce()>>,
+    /// The client application of the shell the window was opened from
+    client: Option<Arc<Mutex<Child>>>,
+}
+
+enum TotpChange {
+    Unchanged,
+    Enrolled { secret: String, recovery_codes: Vec<String> },
+    Disabled,
 }
 
 impl WinKioskSettings {
@@ -20,6 +30,18 @@ impl WinKioskSettings {
         Self::default()
     }
 
+    /// Called once the window is on the screen.
+    pub fn on_shown(mut self, on_shown: impl FnOnce() + 'static) -> Self {
+        self.on_shown = Some(Box::new(on_shown));
+        self
+    }
+
+    /// Stopped before an update restarts the shell.
+    pub fn with_client(mut self, client: Arc<Mutex<Child>>) -> Self {
+        self.client = Some(client);
+        self
+    }
+
     pub fn run(self) {
         let ui = SettingsWindow::new().unwrap();
 
@@ -46,7 +68,17 @@ impl WinKioskSettings {
             if let Some(password) = config.password.clone() {
                 ui.set_app_password(password.into());
             }
+            if let Some(operator_pin) = config.operator_pin.clone() {
+                ui.set_operator_pin(operator_pin.into());
+            }
+            if let Some(recovery_code) = config.recovery_code.clone() {
+                ui.set_recovery_code(recovery_code.into());
+            }
+            ui.set_totp_enabled(config.totp_secret.is_some());
+            ui.set_totp_skew_steps(config.totp_skew_steps as i32);
         }
+
+        let totp_change = Rc::new(RefCell::new(TotpChange::Unchanged));
         
         ui.on_user_selected({
             let ui_handle = ui.as_weak();
@@ -70,6 +102,36 @@ impl WinKioskSettings {
                 }
             }
         });
+        ui.on_totp_enroll({
+            let ui_handle = ui.as_weak();
+            let totp_change = totp_change.clone();
+            move || {
+                let ui = ui_handle.unwrap();
+                let secret = totp::generate_secret();
+                let recovery_codes = totp::generate_recovery_codes();
+                let account = if ui.get_selected_user().is_empty() { whoami::username() } else { ui.get_selected_user().to_string() };
+
+                match render_qr_code(&totp::provisioning_uri(&secret, &account)) {
+                    Ok(image) => ui.set_totp_qr_code(image),
+                    Err(e) => Self::message_box_err(&ui, e),
+                }
+                ui.set_totp_secret(secret.clone().into());
+                ui.set_totp_recovery_codes(recovery_codes.join("\n").into());
+                ui.set_totp_code("".into());
+                ui.set_totp_enrolling(true);
+                *totp_change.borrow_mut() = TotpChange::Enrolled { secret, recovery_codes };
+            }
+        });
+        ui.on_totp_disable({
+            let ui_handle = ui.as_weak();
+            let totp_change = totp_change.clone();
+            move || {
+                let ui = ui_handle.unwrap();
+                ui.set_totp_enrolling(false);
+                ui.set_totp_enabled(false);
+                *totp_change.borrow_mut() = TotpChange::Disabled;
+            }
+        });
         ui.on_request_cancel_close({
             let ui_handle = ui.as_weak();
             move || {
@@ -79,16 +141,30 @@ impl WinKioskSettings {
         });
         ui.on_request_save_close({
             let ui_handle = ui.as_weak();
+            let totp_change = totp_change.clone();
             move || {
                 let ui = ui_handle.unwrap();
 
                 let app_path = ui.get_client_application().to_string();
                 let password = ui.get_app_password().to_string();
+                let operator_pin = ui.get_operator_pin().to_string();
+                let recovery_code = ui.get_recovery_code().to_string();
+                let skew_steps = ui.get_totp_skew_steps().max(0) as u64;
                 if password.is_empty() {
                     Self::message_box(&ui, "Password must be set.");
+                } else if matches!(&*totp_change.borrow(), TotpChange::Enrolled { secret, .. } if totp::verify(secret, &ui.get_totp_code(), skew_steps, unix_now()).is_none()) {
+                    Self::message_box(&ui, "Enter the current code of the authenticator app to finish the enrollment.");
                 } else {
                     let user_name = ui.get_selected_user();
-                    ConfigManager::set_settings(user_name.as_str(), app_path, password);
+                    ConfigManager::set_settings(user_name.as_str(), app_path, password, operator_pin, recovery_code);
+                    match totp_change.replace(TotpChange::Unchanged) {
+                        TotpChange::Enrolled { secret, recovery_codes } => ConfigManager::set_totp(user_name.as_str(), Some(secret), recovery_codes, skew_steps),
+                        TotpChange::Disabled => ConfigManager::set_totp(user_name.as_str(), None, Vec::new(), skew_steps),
+                        TotpChange::Unchanged => {
+                            let config = ConfigManager::load_config(user_name.as_str());
+                            ConfigManager::set_totp(user_name.as_str(), config.totp_secret, config.totp_recovery_codes, skew_steps);
+                        },
+                    }
                     let exe = env::current_exe();
                     let result = write_user_shell(user_name.as_str(), exe.unwrap().to_str().unwrap());
                     if let Err(e) = result {
@@ -99,9 +175,86 @@ impl WinKioskSettings {
             }
         });
 
-        check_for_update();
+        if let Some(rolled_back) = updater::take_rolled_back_version() {
+            ui.set_update_status(rolled_back.message().into());
+        }
+        Self::show_update_info(&ui);
+        // Opened from the shell, this is the checker of the shell; a second one would download the same files again.
+        let update_checker = updater::UpdateChecker::shared();
+        let update_subscription = update_checker.subscribe({
+            let ui_handle = ui.as_weak();
+            move |status| {
+                let ui_handle = ui_handle.clone();
+                let _ = slint::invoke_from_event_loop(move || {
+                    if let Some(ui) = ui_handle.upgrade() {
+                        ui.set_update_status(status.message().into());
+                        ui.set_update_downloading(status.progress().is_some());
+                        ui.set_update_progress(status.progress().unwrap_or_default());
+                        if status.progress().is_none() {
+                            Self::show_update_info(&ui);
+                        }
+                    }
+                });
+            }
+        });
+        ui.on_check_now({
+            let ui_handle = ui.as_weak();
+            let signal = update_checker.signal();
+            move || {
+                let ui = ui_handle.unwrap();
+                ui.set_update_status("Checking for updates".into());
+                signal.check_now();
+            }
+        });
+        ui.on_install_now({
+            let ui_handle = ui.as_weak();
+            let client = self.client.clone();
+            move || {
+                let ui = ui_handle.unwrap();
+                match updater::install_update_and_restart(client.as_deref()) {
+                    Ok(Some(version)) => {
+                        ui.set_update_status(format!("Version {} installed, restarting", version).into());
+                        let _ = slint::quit_event_loop();
+                    },
+                    Ok(None) => ui.set_update_status("No update ready".into()),
+                    Err(e) => ui.set_update_status(format!("Update failed: {}", e).into()),
+                }
+                Self::show_update_info(&ui);
+            }
+        });
+        let _ = ui.show();
+        if let Some(on_shown) = self.on_shown {
+            on_shown();
+        }
+        let _ = slint::run_event_loop();
+        let _ = ui.hide();
+        drop(update_subscription);
+        drop(update_checker);
+
+        // Nobody uses the kiosk while the settings are open, so a staged update can replace the executable right away.
+        // A failure is in the audit log as update_install_failed.
+        let _ = updater::install_staged_update();
+    }
+
+    fn show_update_info(ui: &SettingsWindow) {
+        ui.set_current_version(env!("APP_VERSION").into());
+        ui.set_update_ready(updater::staged_update().is_some());
+        let Some(last_check) = updater::last_check() else {
+            ui.set_last_check("never".into());
+            return;
+        };
 
-        let _ = ui.run();
+        let checked_at = chrono::DateTime::from_timestamp(last_check.checked_at as i64, 0)
+            .map(|checked_at| checked_at.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M").to_string())
+            .unwrap_or_default();
+        ui.set_last_check(format!("{}, {}", checked_at, last_check.result).into());
+        let latest_version = match (&last_check.latest_version, &last_check.published_at) {
+            (Some(version), Some(published_at)) => format!("{} ({})", version, published_at.split('T').next().unwrap_or_default()),
+            (Some(version), None) => version.clone(),
+            _ => "unknown".to_string(),
+        };
+        ui.set_latest_version(latest_version.into());
+        ui.set_release_notes(last_check.release_notes.as_deref().map(release::release_notes_text).unwrap_or_default().into());
     }
 
     fn message_box_err(ui: &SettingsWindow, e: Box<dyn Error>) {
@@ -115,6 +268,29 @@ impl WinKioskSettings {
     }
 }
 
+fn render_qr_code(data: &str) -> Result<Image, Box<dyn Error>> {
+    const MODULE_SIZE: usize = 4;
+    const QUIET_ZONE: usize = 4;
+
+    let code = QrCode::new(data)?;
+    let modules = code.width();
+    let colors = code.to_colors();
+    let size = (modules + 2 * QUIET_ZONE) * MODULE_SIZE;
+
+    let mut buffer = SharedPixelBuffer::<Rgb8Pixel>::new(size as u32, size as u32);
+    for (index, pixel) in buffer.make_mut_slice().iter_mut().enumerate() {
+        let module_x = (index % size) / MODULE_SIZE;
+        let module_y = (index / size) / MODULE_SIZE;
+        let dark = module_x >= QUIET_ZONE && module_y >= QUIET_ZONE
+            && module_x < modules + QUIET_ZONE && module_y < modules + QUIET_ZONE
+            && colors[(module_y - QUIET_ZONE) * modules + module_x - QUIET_ZONE] == Color::Dark;
+        let value = if dark { 0 } else { 255 };
+        *pixel = Rgb8Pixel { r: value, g: value, b: value };
+    }
+
+    Ok(Image::from_rgb8(buffer))
+}
+
 fn get_info(user_name: &str) -> String {
     let current_user_name = whoami::username();
     if user_name.is_empty() || current_user_name == user_name {
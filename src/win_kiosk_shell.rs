@@ -1,37 +1,79 @@
-use std::env;
-use std::path::PathBuf;
+use std::error::Error;
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
 use std::sync::Mutex;
 use std::{path::Path, thread};
-use std::time::Duration;
-use slint::{self, ComponentHandle};
+use std::time::{Duration, Instant};
+use slint::{self, ComponentHandle, Timer, TimerMode};
 use sysinfo::System;
-use std::sync::{Arc, atomic::{AtomicBool, Ordering}};
+use std::sync::{Arc, atomic::{AtomicBool, AtomicU32, Ordering}};
 
 use winapi::um::winuser::{FindWindowA, SetForegroundWindow, GetForegroundWindow};
 use std::ffi::CString;
 use std::ptr::null_mut;
 
-use crate::mainconfig::ConfigManager;
-use crate::{release, win_kiosk_settings};
+use crate::admin_gesture::{AdminGesture, GestureDetector, HotkeyListener};
+use crate::audit_log::AuditLog;
+use crate::challenge_response;
+use crate::device;
+use crate::login_guard::{unix_now, AdminSession, LoginGuard, Role};
+use crate::machinepolicy::MachinePolicy;
+use crate::removable_drives::DriveWatcher;
+use crate::unlock_token::UnlockToken;
+use crate::mainconfig::{ConfigManager, MainConfig};
+use crate::{app_deployment, maintenance_window, update_cache, updater, win_kiosk_settings};
+use crate::management_api::{KioskStatus, ManagementApi, ManagementCommand};
 
 slint::slint!{
     import { Button, LineEdit } from "std-widgets.slint";
 
     export component KioskWindow inherits Window {
         in-out property <string> password_text;
+        in-out property <string> totp_code;
+        in-out property <bool> totp_required;
+        in-out property <string> support_challenge;
+        in-out property <string> device_id;
         in-out property <string> version;
+        in-out property <string> status_text;
+        in-out property <string> status_info;
+        in-out property <string> update_status;
+        in-out property <string> update_warning;
+        in-out property <bool> operator_unlocked;
+        in-out property <bool> admin_unlocked;
+        in-out property <bool> maintenance_mode;
+        in-out property <bool> panel_visible;
 
+        callback pointer_down(float, float, float, float);
+        callback pointer_up();
+        callback reveal_panel();
+        callback unlock();
+        callback request_support();
+        callback usb_unlocked(int);
+        callback lock();
         callback close();
         callback settings();
-        callback start_app();
+        callback restart_app();
         callback reboot();
+        callback show_status();
+        callback toggle_maintenance();
+        callback install_now();
 
         Rectangle {
             background: black;
             width: 100%;
             height: 100%;
 
-            VerticalLayout {
+            TouchArea {
+                pointer-event(event) => {
+                    if (event.kind == PointerEventKind.down) {
+                        root.pointer_down(self.mouse-x / 1px, self.mouse-y / 1px, self.width / 1px, self.height / 1px);
+                    } else if (event.kind == PointerEventKind.up) {
+                        root.pointer_up();
+                    }
+                }
+            }
+
+            if panel_visible : VerticalLayout {
                 width: 10%;
                 height: 10%;
 
@@ -39,32 +81,104 @@ slint::slint!{
                     text: version;
                 }
 
-                LineEdit {
+                if !operator_unlocked : LineEdit {
                     text <=> password_text;
                     input-type: password;
+                    accepted => {root.unlock();}
+                }
+                if !operator_unlocked && support_challenge != "" : Text {
+                    text: "Device: " + device_id + "\nChallenge: " + support_challenge;
+                    color: white;
+                }
+                if !operator_unlocked && totp_required && support_challenge == "" : LineEdit {
+                    text <=> totp_code;
+                    placeholder-text: "Code";
+                    accepted => {root.unlock();}
+                }
+                if !operator_unlocked : Button {
+                    text: "🔓";
+                    clicked => {root.unlock();}
+                }
+                if !operator_unlocked : Button {
+                    text: "Support";
+                    clicked => {root.request_support();}
+                }
+
+                Text {
+                    text: status_text;
+                    color: #ff4040;
+                    wrap: word-wrap;
                 }
 
-                Button {
+                if admin_unlocked : Button {
                     text: "X";
                     clicked => {root.close();}
                 }
-                Button {
+                if admin_unlocked : Button {
                     text: "S";
                     clicked => {root.settings();}
                 }
-                Button {
-                    text: "Start";
-                    clicked => {root.start_app();}
+                if admin_unlocked : Button {
+                    text: maintenance_mode ? "Maintenance off" : "Maintenance on";
+                    clicked => {root.toggle_maintenance();}
                 }
-                Button {
+                if admin_unlocked : Button {
+                    text: "Install update";
+                    clicked => {root.install_now();}
+                }
+                if operator_unlocked : Button {
+                    text: "Restart app";
+                    clicked => {root.restart_app();}
+                }
+                if operator_unlocked : Button {
                     text: "🔁";
                     clicked => {root.reboot();}
                 }
+                if operator_unlocked : Button {
+                    text: "Status";
+                    clicked => {root.show_status();}
+                }
+                if operator_unlocked : Button {
+                    text: "🔒";
+                    clicked => {root.lock();}
+                }
+
+                Text {
+                    text: status_info;
+                    color: white;
+                    wrap: word-wrap;
+                }
+                if operator_unlocked : Text {
+                    text: update_status;
+                    color: white;
+                    wrap: word-wrap;
+                }
+            }
+
+            if update_warning != "" : Rectangle {
+                y: 0;
+                height: 10%;
+                background: #202020;
+
+                Text {
+                    text: update_warning;
+                    color: #ffc040;
+                    font-size: 24px;
+                    horizontal-alignment: center;
+                    vertical-alignment: center;
+                }
             }
         }
     }
 }
 
+const UPDATE_INSTALL_INTERVAL: Duration = Duration::from_secs(60);
+const HEALTH_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+const MANAGEMENT_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Restarts of the client application by the shell, reported by the management API
+static APP_RESTARTS: AtomicU32 = AtomicU32::new(0);
+
 #[derive()]
 pub struct WinKioskShell {
     process: Arc<Mutex<std::process::Child>>,
@@ -76,16 +190,24 @@ impl WinKioskShell {
     }
 
     pub fn run(self) {
+        let started_at = Instant::now();
         let running = Arc::new(AtomicBool::new(true));
+        let maintenance = Arc::new(AtomicBool::new(false));
+
+        let config = ConfigManager::load_config(&"");
+        let client_application_name = Arc::new(Mutex::new(get_client_application_name(&config)));
 
         let running_clone = running.clone();
+        let maintenance_clone = maintenance.clone();
+        let client_application_name_clone = client_application_name.clone();
         let process_worker = thread::spawn(move || {
-            let client_application = &ConfigManager::load_config(&"").client_application.clone();
-            let client_application_name = client_application.as_ref().and_then(|path| {
-                Path::new(path).file_name().and_then(|name| name.to_str()).map(|s| s.to_lowercase())
-            });
-
             while running_clone.load(Ordering::SeqCst) {
+                if maintenance_clone.load(Ordering::SeqCst) {
+                    thread::sleep(Duration::from_secs(1));
+                    continue;
+                }
+
+                let client_application_name = client_application_name_clone.lock().unwrap().clone();
                 let mut system = System::new_all();
 
                 system.refresh_all();
@@ -104,53 +226,412 @@ impl WinKioskShell {
             }
         });
 
+        let login_guard = Arc::new(Mutex::new(LoginGuard::load()));
+        let session: Arc<Mutex<Option<AdminSession>>> = Arc::new(Mutex::new(None));
+        let panel_timer = Rc::new(Timer::default());
+        let admin_gesture = config.admin_gesture.clone();
+        let gesture_detector = Rc::new(RefCell::new(GestureDetector::new(admin_gesture.clone())));
+
         let window = KioskWindow::new().unwrap();
         window.set_version(env!("APP_VERSION").into());
+        window.set_totp_required(config.totp_secret.is_some());
+        window.set_device_id(device::device_id().into());
+        window.on_reveal_panel({
+            let ui_handle = window.as_weak();
+            let panel_timer = panel_timer.clone();
+            let session = session.clone();
+            move || {
+                let ui = ui_handle.unwrap();
+                keep_panel_open(&ui, &panel_timer, &session);
+            }
+        });
+        window.on_pointer_down({
+            let ui_handle = window.as_weak();
+            let gesture_detector = gesture_detector.clone();
+            move |x, y, width, height| {
+                if gesture_detector.borrow_mut().pointer_down(x, y, width, height) {
+                    ui_handle.unwrap().invoke_reveal_panel();
+                }
+            }
+        });
+        window.on_pointer_up({
+            let ui_handle = window.as_weak();
+            let gesture_detector = gesture_detector.clone();
+            move || {
+                if gesture_detector.borrow_mut().pointer_up() {
+                    ui_handle.unwrap().invoke_reveal_panel();
+                }
+            }
+        });
+        window.on_unlock({
+            let ui_handle = window.as_weak();
+            let login_guard = login_guard.clone();
+            let panel_timer = panel_timer.clone();
+            let session = session.clone();
+            move || {
+                let ui = ui_handle.unwrap();
+                let admin_session = check_password(&ui, &login_guard);
+                set_session(&ui, &session, admin_session);
+                keep_panel_open(&ui, &panel_timer, &session);
+            }
+        });
+        window.on_request_support({
+            let ui_handle = window.as_weak();
+            let panel_timer = panel_timer.clone();
+            let session = session.clone();
+            move || {
+                let ui = ui_handle.unwrap();
+                let challenge = challenge_response::generate_challenge();
+                AuditLog::write("support_challenge", &challenge);
+                ui.set_support_challenge(challenge.into());
+                keep_panel_open(&ui, &panel_timer, &session);
+            }
+        });
+        window.on_usb_unlocked({
+            let ui_handle = window.as_weak();
+            let panel_timer = panel_timer.clone();
+            let session = session.clone();
+            move |minutes| {
+                let ui = ui_handle.unwrap();
+                let admin_session = AdminSession::limited(Role::Administrator, Duration::from_secs(minutes.max(1) as u64 * 60));
+                set_session(&ui, &session, Some(admin_session));
+                ui.set_status_text("USB unlock".into());
+                keep_panel_open(&ui, &panel_timer, &session);
+            }
+        });
+        window.on_lock({
+            let ui_handle = window.as_weak();
+            let panel_timer = panel_timer.clone();
+            let session = session.clone();
+            move || {
+                let ui = ui_handle.unwrap();
+                hide_panel(&ui, &panel_timer, &session);
+            }
+        });
         window.on_close({
             let ui_handle = window.as_weak();
+            let session = session.clone();
             move || {
                 let ui = ui_handle.unwrap();
-                let password = ui.get_password_text().to_string();
-                let check = ConfigManager::load_config(&"").password.clone();
-                if check.is_none() || check.unwrap() == password {
+                if has_role(&session, Role::Administrator) {
+                    AuditLog::write("exit_shell", "");
                     let _ = ui.hide();
                 }
             }
         });
         window.on_settings({
             let ui_handle = window.as_weak();
+            let session = session.clone();
+            let process_clone = self.process.clone();
             move || {
                 let ui = ui_handle.unwrap();
-                let password = ui.get_password_text().to_string();
-                let check = ConfigManager::load_config(&"").password.clone();
-                if check.is_none() || check.unwrap() == password {
-                    win_kiosk_settings::WinKioskSettings::new().run();
+                if has_role(&session, Role::Administrator) {
+                    AuditLog::write("open_settings", "");
+                    win_kiosk_settings::WinKioskSettings::new().with_client(process_clone.clone()).run();
                     let _ = ui.hide();
                 }
             }
         });
-        window.on_start_app({
+        window.on_toggle_maintenance({
+            let ui_handle = window.as_weak();
+            let panel_timer = panel_timer.clone();
+            let session = session.clone();
+            let maintenance = maintenance.clone();
+            move || {
+                let ui = ui_handle.unwrap();
+                if has_role(&session, Role::Administrator) {
+                    keep_panel_open(&ui, &panel_timer, &session);
+                    set_maintenance(&ui, &maintenance, !maintenance.load(Ordering::SeqCst));
+                }
+            }
+        });
+        window.on_install_now({
+            let ui_handle = window.as_weak();
+            let process_clone = self.process.clone();
+            let session = session.clone();
+            move || {
+                if !has_role(&session, Role::Administrator) {
+                    return;
+                }
+
+                let ui = ui_handle.unwrap();
+                if app_deployment::staged_app().is_none() && updater::staged_update().is_none() {
+                    ui.set_update_status("No update ready".into());
+                    return;
+                }
+                AuditLog::write("install_now", "");
+                if app_deployment::staged_app().is_some() {
+                    match install_app_update(&process_clone) {
+                        Ok(Some(version)) => ui.set_update_status(format!("Application {} installed", version).into()),
+                        Ok(None) => {},
+                        Err(e) => ui.set_update_status(format!("Application update failed: {}", e).into()),
+                    }
+                }
+                if let Err(e) = install_update_and_restart(&process_clone) {
+                    ui.set_update_status(format!("Update failed: {}", e).into());
+                }
+            }
+        });
+        window.on_restart_app({
             let process_clone = self.process.clone();
             let running_clone = running.clone();
+            let session = session.clone();
+            move || {
+                if !running_clone.load(Ordering::SeqCst) || !has_role(&session, Role::Operator) {
+                    return;
+                }
+
+                AuditLog::write("restart_app", "");
+                if let Err(e) = restart_client(&process_clone) {
+                    AuditLog::write("restart_app_failed", &e.to_string());
+                }
+            }
+        });
+        window.on_reboot({
+            let session = session.clone();
             move || {
-                if !running_clone.load(Ordering::SeqCst) {
+                if !has_role(&session, Role::Operator) {
                     return;
                 }
 
+                AuditLog::write("reboot", "");
+                reboot();
+            }
+        });
+        window.on_show_status({
+            let ui_handle = window.as_weak();
+            let process_clone = self.process.clone();
+            let panel_timer = panel_timer.clone();
+            let session = session.clone();
+            let maintenance = maintenance.clone();
+            move || {
+                let ui = ui_handle.unwrap();
+                if !has_role(&session, Role::Operator) {
+                    return;
+                }
+                keep_panel_open(&ui, &panel_timer, &session);
+
+                let app_state = app_state(&process_clone);
+                let uptime = started_at.elapsed().as_secs();
+                ui.set_status_info(format!(
+                    "Version: {}\nApp: {}\nUptime: {}:{:02}\nMaintenance: {}",
+                    env!("APP_VERSION"),
+                    app_state,
+                    uptime / 3600,
+                    (uptime / 60) % 60,
+                    if maintenance.load(Ordering::SeqCst) { "on" } else { "off" },
+                ).into());
+            }
+        });
+        let _hotkey_listener = match &admin_gesture {
+            AdminGesture::Hotkey(hotkey) => {
+                let ui_handle = window.as_weak();
+                HotkeyListener::spawn(hotkey, move || {
+                    let ui_handle = ui_handle.clone();
+                    let _ = slint::invoke_from_event_loop(move || {
+                        if let Some(ui) = ui_handle.upgrade() {
+                            ui.invoke_reveal_panel();
+                        }
+                    });
+                })
+            },
+            _ => None,
+        };
+        let _drive_watcher = DriveWatcher::spawn({
+            let ui_handle = window.as_weak();
+            let login_guard = login_guard.clone();
+            move |drive| {
+                if let Some(token) = UnlockToken::read(&drive) {
+                    let policy = MachinePolicy::load();
+                    let device_id = device::device_id();
+                    let now = unix_now();
+                    let verified = match policy.unlock_public_key.as_deref() {
+                        Some(public_key) => token.verify(public_key, &device_id, now),
+                        None => Err("no unlock public key in the machine policy".into()),
+                    }.and_then(|()| if login_guard.lock().unwrap().consume_unlock_token(&token, now) {
+                        Ok(())
+                    } else {
+                        Err("token was already used".into())
+                    });
+
+                    match verified {
+                        Ok(()) => {
+                            AuditLog::write("usb_unlock", &format!("drive {} expires {} session {} min", drive.display(), token.expires, policy.usb_unlock_minutes));
+                            let ui_handle = ui_handle.clone();
+                            let minutes = policy.usb_unlock_minutes.min(i32::MAX as u64) as i32;
+                            let _ = slint::invoke_from_event_loop(move || {
+                                if let Some(ui) = ui_handle.upgrade() {
+                                    ui.invoke_usb_unlocked(minutes);
+                                }
+                            });
+                        },
+                        Err(e) => AuditLog::write("usb_unlock_rejected", &format!("drive {}: {}", drive.display(), e)),
+                    }
+                }
+
+                // Staging reads and verifies the whole package, which must not hold up the next drive.
+                let ui_handle = ui_handle.clone();
+                thread::spawn(move || check_usb_update(&drive, &ui_handle));
+            }
+        });
+        let update_timer = Timer::default();
+        let forced_install_timer = Rc::new(Timer::default());
+        update_timer.start(TimerMode::Repeated, UPDATE_INSTALL_INTERVAL, {
+            let ui_handle = window.as_weak();
+            let process_clone = self.process.clone();
+            move || {
                 let config = ConfigManager::load_config(&"");
-                let client_application = config.client_application;
-                let app_path = PathBuf::from(if let Some(app) = client_application {app.clone()} else {"".to_owned()});
-                let mut proc = process_clone.lock().unwrap();
-                *proc = std::process::Command::new(app_path).spawn().unwrap();
+                let ui = ui_handle.unwrap();
+                if app_deployment::staged_app().is_some() && maintenance_window::is_maintenance_time(&config) {
+                    match install_app_update(&process_clone) {
+                        Ok(Some(version)) => ui.set_update_status(format!("Application {} installed", version).into()),
+                        Ok(None) => {},
+                        Err(e) => ui.set_update_status(format!("Application update failed: {}", e).into()),
+                    }
+                }
 
-                check_for_update();
+                let Some(staged) = updater::scheduled_update() else {
+                    return;
+                };
+                if maintenance_window::is_maintenance_time(&config) {
+                    match install_update_and_restart(&process_clone) {
+                        Ok(()) => ui.set_update_status("Restarting for the update".into()),
+                        Err(e) => ui.set_update_status(format!("Update failed: {}", e).into()),
+                    }
+                } else if updater::is_install_overdue(&config) && !forced_install_timer.running() {
+                    // Deferred too long: warn whoever is in front of the kiosk, then install anyway.
+                    let warning = Duration::from_secs(config.update_warning_secs);
+                    let restart_at = chrono::Local::now() + warning;
+                    ui.set_update_warning(format!("This kiosk restarts for an update at {}", restart_at.format("%H:%M")).into());
+                    AuditLog::write("update_forced", &staged.version);
+
+                    let ui_handle = ui.as_weak();
+                    let process_clone = process_clone.clone();
+                    forced_install_timer.start(TimerMode::SingleShot, warning, move || {
+                        let ui = ui_handle.unwrap();
+                        ui.set_update_warning("".into());
+                        if let Err(e) = install_update_and_restart(&process_clone) {
+                            ui.set_update_status(format!("Update failed: {}", e).into());
+                        }
+                    });
+                }
             }
         });
-        window.on_reboot({
+        if let Some(rolled_back) = updater::take_rolled_back_version() {
+            window.set_update_status(rolled_back.message().into());
+        }
+        let update_checker = updater::UpdateChecker::shared();
+        let _update_subscription = update_checker.subscribe({
+            let ui_handle = window.as_weak();
+            move |status| {
+                let ui_handle = ui_handle.clone();
+                let _ = slint::invoke_from_event_loop(move || {
+                    if let Some(ui) = ui_handle.upgrade() {
+                        ui.set_update_status(status.message().into());
+                    }
+                });
+            }
+        });
+        let update_cache_server = Rc::new(RefCell::new(start_update_cache(&config)));
+        let healthy_after = Rc::new(Cell::new(Duration::from_secs(config.update_healthy_secs)));
+        let management_timer = Timer::default();
+        let management_api = start_management_api({
+            let process_clone = self.process.clone();
+            let maintenance = maintenance.clone();
+            move || KioskStatus {
+                version: env!("APP_VERSION").to_string(),
+                uptime_secs: started_at.elapsed().as_secs(),
+                app_state: app_state(&process_clone).to_string(),
+                app_restarts: APP_RESTARTS.load(Ordering::SeqCst),
+                maintenance: maintenance.load(Ordering::SeqCst),
+                staged_update: updater::staged_update().map(|staged| staged.version),
+                last_update_check: updater::last_check(),
+            }
+        });
+        if let Some(management_api) = management_api {
+            let ui_handle = window.as_weak();
+            let process_clone = self.process.clone();
+            let maintenance = maintenance.clone();
+            let gesture_detector = gesture_detector.clone();
+            let client_application_name = client_application_name.clone();
+            let update_cache_server = update_cache_server.clone();
+            let healthy_after = healthy_after.clone();
+            management_timer.start(TimerMode::Repeated, MANAGEMENT_POLL_INTERVAL, move || {
+                let ui = ui_handle.unwrap();
+                while let Some(pending) = management_api.take_command() {
+                    let mut result = Ok(());
+                    match pending.command {
+                        ManagementCommand::RestartApp => {
+                            if let Err(e) = restart_client(&process_clone) {
+                                AuditLog::write("restart_app_failed", &e.to_string());
+                                result = Err(e.to_string());
+                            }
+                        },
+                        ManagementCommand::Reboot => reboot(),
+                        ManagementCommand::EnterMaintenance => set_maintenance(&ui, &maintenance, true),
+                        ManagementCommand::LeaveMaintenance => set_maintenance(&ui, &maintenance, false),
+                        // Everything else is read from the configuration whenever it is used.
+                        ManagementCommand::ReloadConfig => {
+                            let config = ConfigManager::load_config(&"");
+                            ui.set_totp_required(config.totp_secret.is_some());
+                            *gesture_detector.borrow_mut() = GestureDetector::new(config.admin_gesture.clone());
+                            *client_application_name.lock().unwrap() = get_client_application_name(&config);
+                            healthy_after.set(Duration::from_secs(config.update_healthy_secs));
+                            // The old server has to release the port first.
+                            *update_cache_server.borrow_mut() = None;
+                            *update_cache_server.borrow_mut() = start_update_cache(&config);
+                        },
+                    }
+                    pending.done(result);
+                }
+            });
+        }
+        // A freshly installed version counts as healthy once the client application ran long enough.
+        let health_timer = Timer::default();
+        if updater::is_update_pending() {
+            let healthy_after = healthy_after.clone();
+            let process_clone = self.process.clone();
+            let mut running_since = Instant::now();
+            let mut healthy = false;
+            health_timer.start(TimerMode::Repeated, HEALTH_CHECK_INTERVAL, move || {
+                if healthy {
+                    return;
+                }
+                if !matches!(process_clone.lock().unwrap().try_wait(), Ok(None)) {
+                    running_since = Instant::now();
+                } else if running_since.elapsed() >= healthy_after.get() {
+                    updater::mark_healthy();
+                    healthy = true;
+                }
+            });
+        }
+        // A new client application version is rolled back if it keeps exiting before it ran long enough.
+        let app_health_timer = Timer::default();
+        app_health_timer.start(TimerMode::Repeated, HEALTH_CHECK_INTERVAL, {
+            let ui_handle = window.as_weak();
+            let healthy_after = healthy_after.clone();
+            let process_clone = self.process.clone();
+            let mut running_since = Instant::now();
             move || {
-                match std::process::Command::new("shutdown").args(&["/r", "/t", "0"]).spawn() {
-                    Ok(_) => println!("Windows reboot."),
-                    Err(e) => eprintln!("Error: {}", e),
+                if !app_deployment::is_app_update_pending() {
+                    running_since = Instant::now();
+                    return;
+                }
+                if matches!(process_clone.lock().unwrap().try_wait(), Ok(None)) {
+                    if running_since.elapsed() >= healthy_after.get() {
+                        app_deployment::mark_app_healthy();
+                    }
+                    return;
+                }
+
+                if let Some(version) = app_deployment::record_app_exit() {
+                    if let Some(ui) = ui_handle.upgrade() {
+                        ui.set_update_status(format!("Application {} kept crashing and was rolled back", version).into());
+                    }
+                }
+                running_since = Instant::now();
+                if let Err(e) = restart_client(&process_clone) {
+                    AuditLog::write("restart_app_failed", &e.to_string());
                 }
             }
         });
@@ -167,6 +648,199 @@ impl WinKioskShell {
     }
 }
 
+fn check_password(ui: &KioskWindow, login_guard: &Mutex<LoginGuard>) -> Option<AdminSession> {
+    let password = ui.get_password_text().to_string();
+    let code = ui.get_totp_code().to_string();
+    let challenge = ui.get_support_challenge().to_string();
+    ui.set_password_text("".into());
+    ui.set_totp_code("".into());
+    ui.set_support_challenge("".into());
+
+    let config = ConfigManager::load_config(&"");
+    if !challenge.is_empty() {
+        let result = login_guard.lock().unwrap().attempt_support_response(&password, &challenge, &config);
+        ui.set_status_text(result.message().into());
+        let minutes = MachinePolicy::load().support_session_minutes;
+        return result.granted_role().map(|role| {
+            AuditLog::write("support_session_started", &format!("{} min", minutes));
+            AdminSession::limited(role, Duration::from_secs(minutes * 60))
+        });
+    }
+
+    let result = login_guard.lock().unwrap().attempt(&password, &code, &config);
+    ui.set_status_text(result.message().into());
+    result.granted_role().map(AdminSession::new)
+}
+
+fn set_session(ui: &KioskWindow, session: &Mutex<Option<AdminSession>>, admin_session: Option<AdminSession>) {
+    *session.lock().unwrap() = admin_session;
+    ui.set_operator_unlocked(admin_session.is_some());
+    ui.set_admin_unlocked(admin_session.is_some_and(|admin_session| admin_session.role == Role::Administrator));
+    ui.set_status_info("".into());
+}
+
+fn keep_panel_open(ui: &KioskWindow, panel_timer: &Timer, session: &Arc<Mutex<Option<AdminSession>>>) {
+    ui.set_panel_visible(true);
+
+    let mut timeout = Duration::from_secs(ConfigManager::load_config(&"").admin_panel_timeout_secs.max(5));
+    if let Some(remaining) = session.lock().unwrap().and_then(|admin_session| admin_session.remaining()) {
+        timeout = timeout.min(remaining);
+    }
+
+    let ui_handle = ui.as_weak();
+    let session = session.clone();
+    panel_timer.start(TimerMode::SingleShot, timeout, move || {
+        if let Some(ui) = ui_handle.upgrade() {
+            *session.lock().unwrap() = None;
+            reset_panel(&ui);
+        }
+    });
+}
+
+fn hide_panel(ui: &KioskWindow, panel_timer: &Timer, session: &Mutex<Option<AdminSession>>) {
+    panel_timer.stop();
+    *session.lock().unwrap() = None;
+    reset_panel(ui);
+}
+
+fn reset_panel(ui: &KioskWindow) {
+    ui.set_panel_visible(false);
+    ui.set_operator_unlocked(false);
+    ui.set_admin_unlocked(false);
+    ui.set_password_text("".into());
+    ui.set_totp_code("".into());
+    ui.set_support_challenge("".into());
+    ui.set_status_text("".into());
+    ui.set_status_info("".into());
+}
+
+fn has_role(session: &Mutex<Option<AdminSession>>, required: Role) -> bool {
+    session.lock().unwrap().is_some_and(|admin_session| admin_session.allows(required))
+}
+
+fn restart_client(process: &Mutex<std::process::Child>) -> Result<(), Box<dyn Error>> {
+    let client_application = ConfigManager::load_config(&"").client_application.unwrap_or_default();
+    let mut proc = process.lock().unwrap();
+    let _ = proc.kill();
+    let _ = proc.wait();
+    *proc = std::process::Command::new(client_application).spawn()?;
+    APP_RESTARTS.fetch_add(1, Ordering::SeqCst);
+    Ok(())
+}
+
+fn app_state(process: &Mutex<std::process::Child>) -> &'static str {
+    match process.lock().unwrap().try_wait() {
+        Ok(None) => "running",
+        _ => "stopped",
+    }
+}
+
+/// In maintenance mode the shell stops enforcing the kiosk and starts Explorer.
+fn set_maintenance(ui: &KioskWindow, maintenance: &AtomicBool, enabled: bool) {
+    maintenance.store(enabled, Ordering::SeqCst);
+    ui.set_maintenance_mode(enabled);
+    AuditLog::write("maintenance_mode", if enabled { "on" } else { "off" });
+    if enabled {
+        let _ = std::process::Command::new("explorer.exe").spawn();
+    }
+}
+
+/// Lower case file name of the client application, whose window is kept in the foreground.
+fn get_client_application_name(config: &MainConfig) -> Option<String> {
+    config.client_application.as_ref().and_then(|path| {
+        Path::new(path).file_name().and_then(|name| name.to_str()).map(|s| s.to_lowercase())
+    })
+}
+
+fn start_update_cache(config: &MainConfig) -> Option<update_cache::UpdateCacheServer> {
+    config.update_cache_port.and_then(|port| {
+        match update_cache::UpdateCacheServer::start(port, config.update_cache_discovery) {
+            Ok(server) => Some(server),
+            Err(e) => {
+                AuditLog::write("update_cache_failed", &e.to_string());
+                None
+            },
+        }
+    })
+}
+
+fn reboot() {
+    match std::process::Command::new("shutdown").args(&["/r", "/t", "0"]).spawn() {
+        Ok(_) => println!("Windows reboot."),
+        Err(e) => eprintln!("Error: {}", e),
+    }
+}
+
+/// The local management API, if the machine policy has a token hash for it.
+fn start_management_api(status: impl Fn() -> KioskStatus + Send + 'static) -> Option<ManagementApi> {
+    let policy = MachinePolicy::load();
+    let token_hash = policy.management_token_hash?;
+    let allowed_commands = policy.management_commands.iter().filter_map(|name| ManagementCommand::from_name(name)).collect();
+    match ManagementApi::start(policy.management_port, token_hash, allowed_commands, status) {
+        Ok(api) => Some(api),
+        Err(e) => {
+            AuditLog::write("management_api_failed", &e.to_string());
+            None
+        },
+    }
+}
+
+/// Installs a staged client application version while the application is stopped.
+fn install_app_update(process: &Mutex<std::process::Child>) -> Result<Option<String>, Box<dyn Error>> {
+    let mut proc = process.lock().unwrap();
+    let _ = proc.kill();
+    let _ = proc.wait();
+    let result = app_deployment::install_staged_app();
+    drop(proc);
+
+    restart_client(process)?;
+    result
+}
+
+/// Stages an update package found on an inserted drive. If the machine policy allows it, it is installed on the same
+/// schedule as a downloaded update, otherwise the panel asks for an administrator, who installs it with "Install update".
+fn check_usb_update(drive: &Path, ui_handle: &slint::Weak<KioskWindow>) {
+    let message = match updater::stage_from_drive(drive) {
+        Ok(None) => return,
+        Ok(Some(updater::UpdateStatus::Staged(staged))) => {
+            AuditLog::write("usb_update_staged", &format!("drive {}: {}", drive.display(), staged.version));
+            if MachinePolicy::load().usb_update_auto_install {
+                let message = format!("Update {} from the USB drive is installed in the next maintenance window", staged.version);
+                let ui_handle = ui_handle.clone();
+                let _ = slint::invoke_from_event_loop(move || {
+                    if let Some(ui) = ui_handle.upgrade() {
+                        ui.set_update_status(message.into());
+                    }
+                });
+                return;
+            }
+            format!("Update {} from the USB drive is ready, log in as administrator to install it", staged.version)
+        },
+        Ok(Some(status)) => status.message(),
+        Err(e) => {
+            AuditLog::write("usb_update_rejected", &format!("drive {}: {}", drive.display(), e));
+            format!("Update from the USB drive rejected: {}", e)
+        },
+    };
+
+    let ui_handle = ui_handle.clone();
+    let _ = slint::invoke_from_event_loop(move || {
+        if let Some(ui) = ui_handle.upgrade() {
+            ui.set_status_text(message.clone().into());
+            ui.set_update_status(message.into());
+            ui.invoke_reveal_panel();
+        }
+    });
+}
+
+/// Only called when nobody uses the kiosk: replaces the executable, stops the client application and starts the new shell.
+fn install_update_and_restart(process: &Mutex<std::process::Child>) -> Result<(), Box<dyn Error>> {
+    if updater::install_update_and_restart(Some(process))?.is_some() {
+        let _ = slint::quit_event_loop();
+    }
+    Ok(())
+}
+
 fn set_focus_to_application(application_name: &str) -> Result<(), String> {
     unsafe {
         let window_name = CString::new(application_name).unwrap();
@@ -184,20 +858,3 @@ fn set_focus_to_application(application_name: &str) -> Result<(), String> {
         return Ok(());
     }
 }
-
-pub fn check_for_update() {
-    if let Ok(release) = release::get_latest_release("audioprog/winkioskshell") {
-        if let Ok(is_newer) = release::is_update_available(&release) {
-            if is_newer {
-                if let Some(asset) = release.assets.first() {
-                    let url_string: &String = &asset.browser_download_url;
-                    if let Ok(mut exe) = env::current_exe() {
-                        exe.pop();
-                        let _ = release::download_latest_release(&url_string, exe.as_path());
-                    }
-                }
-            }
-        }
-    }
-}
-